@@ -14,7 +14,7 @@ mod message;
 mod substream;
 
 pub use crate::{
-    addr::{AddrKnown, AddressManager, RawAddr},
+    addr::{is_reachable, AddrKnown, AddressManager},
     message::{DiscoveryMessage, Node, Nodes},
     substream::{Direction, Substream, SubstreamKey, SubstreamValue},
 };
@@ -142,8 +142,8 @@ impl<M: AddressManager> Stream for Discovery<M> {
             }
 
             if value.announce {
-                if let RemoteAddress::Listen(addr) = value.remote_addr {
-                    announce_addrs.push(RawAddr::from(addr));
+                if let RemoteAddress::Listen(ref addr) = value.remote_addr {
+                    announce_addrs.push(addr.clone());
                 }
                 value.announce = false;
             }
@@ -163,8 +163,8 @@ impl<M: AddressManager> Stream for Discovery<M> {
                         if value.announce_addrs.len() < 10
                             && !value.addr_known.contains(&announce_addr)
                         {
-                            value.announce_addrs.push(announce_addr);
-                            value.addr_known.insert(announce_addr);
+                            value.announce_addrs.push(announce_addr.clone());
+                            value.addr_known.insert(announce_addr.clone());
                         }
                     }
                 }
@@ -203,7 +203,7 @@ impl<M: AddressManager> Stream for Discovery<M> {
             Some((_key, nodes)) => {
                 for node in nodes.items.into_iter() {
                     for addr in node.addresses.into_iter() {
-                        self.addr_mgr.add_new(addr.socket_addr());
+                        self.addr_mgr.add_new(addr);
                     }
                 }
                 Ok(Async::Ready(Some(())))
@@ -6,7 +6,7 @@ use serde_derive::{Deserialize, Serialize};
 use tokio::codec::length_delimited::LengthDelimitedCodec;
 use tokio::codec::{Decoder, Encoder};
 
-use crate::addr::RawAddr;
+use p2p::multiaddr::Multiaddr;
 
 pub(crate) struct DiscoveryCodec {
     inner: LengthDelimitedCodec,
@@ -76,7 +76,27 @@ pub struct Nodes {
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
 pub struct Node {
-    pub(crate) addresses: Vec<RawAddr>,
+    #[serde(with = "multiaddrs")]
+    pub(crate) addresses: Vec<Multiaddr>,
+}
+
+/// Serialize addresses in the binary form of multiaddr
+mod multiaddrs {
+    use p2p::multiaddr::Multiaddr;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(addrs: &[Multiaddr], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(addrs.iter().map(Multiaddr::to_bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Multiaddr>, D::Error> {
+        Vec::<Vec<u8>>::deserialize(deserializer)?
+            .into_iter()
+            .map(|bytes| Multiaddr::from_bytes(&bytes).map_err(D::Error::custom))
+            .collect()
+    }
 }
 
 impl std::fmt::Display for DiscoveryMessage {
@@ -1,6 +1,5 @@
 use std::collections::VecDeque;
 use std::io;
-use std::net::SocketAddr;
 use std::time::Duration;
 
 use bytes::{BufMut, BytesMut};
@@ -9,17 +8,20 @@ use futures::{
     Async, AsyncSink, Poll, Sink, Stream,
 };
 use log::{debug, trace, warn};
+use p2p::multiaddr::{multiaddr_to_ip, Multiaddr, Protocol};
 use p2p::service::{Message, ServiceTask};
 use p2p::session::{ProtocolId, SessionId};
+use p2p::Priority;
 use tokio::codec::Framed;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::timer::Interval;
 
-use crate::addr::{AddrKnown, AddressManager, RawAddr};
+use crate::addr::{is_reachable, multiaddr_port, AddrKnown, AddressManager};
 use crate::message::{DiscoveryCodec, DiscoveryMessage, Node, Nodes};
 
 // FIXME: should be a more high level version number
-const VERSION: u32 = 0;
+// Version 1 encodes the node addresses as multiaddr bytes
+const VERSION: u32 = 1;
 // The maximum number of new addresses to accumulate before announcing.
 const MAX_ADDR_TO_SEND: usize = 1000;
 // Every 24 hours send announce nodes message
@@ -80,6 +82,7 @@ impl io::Write for StreamHandle {
                 id: self.session_id,
                 proto_id: self.proto_id,
                 data: buf.to_vec(),
+                priority: Priority::Normal,
             },
         };
         self.sender
@@ -113,7 +116,7 @@ pub struct SubstreamValue {
     // FIXME: Remote listen address, resolved by id protocol
     pub(crate) remote_addr: RemoteAddress,
     pub(crate) announce: bool,
-    pub(crate) announce_addrs: Vec<RawAddr>,
+    pub(crate) announce_addrs: Vec<Multiaddr>,
     timer_future: Interval,
     received_get_nodes: bool,
     received_nodes: bool,
@@ -125,7 +128,7 @@ impl SubstreamValue {
         direction: Direction,
         stream: StreamHandle,
         max_known: usize,
-        remote_addr: SocketAddr,
+        remote_addr: Multiaddr,
         listen_port: Option<u16>,
     ) -> SubstreamValue {
         let mut pending_messages = VecDeque::default();
@@ -137,7 +140,7 @@ impl SubstreamValue {
                 count: MAX_ADDR_TO_SEND as u32,
                 listen_port,
             });
-            addr_known.insert(RawAddr::from(remote_addr));
+            addr_known.insert(remote_addr.clone());
 
             RemoteAddress::Listen(remote_addr)
         } else {
@@ -195,10 +198,19 @@ impl SubstreamValue {
         addr_mgr: &mut M,
     ) -> Result<Option<Nodes>, io::Error> {
         match message {
-            DiscoveryMessage::GetNodes { listen_port, .. } => {
+            DiscoveryMessage::GetNodes {
+                version,
+                listen_port,
+                ..
+            } => {
+                if version != VERSION {
+                    // The remote can't decode the nodes of this version
+                    warn!("Unsupported discovery version {}", version);
+                    return Err(io::ErrorKind::InvalidData.into());
+                }
                 if self.received_get_nodes {
                     // TODO: misbehavior
-                    if addr_mgr.misbehave(self.remote_addr.into_inner(), 111) < 0 {
+                    if addr_mgr.misbehave(self.remote_addr.inner().clone(), 111) < 0 {
                         // TODO: more clear error type
                         warn!("Already received get nodes");
                         return Err(io::ErrorKind::Other.into());
@@ -207,8 +219,8 @@ impl SubstreamValue {
                     /// change client random outbound port to client listen port
                     debug!("listen port: {:?}", listen_port);
                     if let Some(port) = listen_port {
-                        self.remote_addr = self.remote_addr.into_listen(port);
-                        self.addr_known.insert(self.remote_addr.into_inner().into());
+                        self.remote_addr = self.remote_addr.to_listen(port);
+                        self.addr_known.insert(self.remote_addr.inner().clone());
                     }
 
                     // TODO: magic number
@@ -222,7 +234,7 @@ impl SubstreamValue {
                     let items = items
                         .into_iter()
                         .map(|addr| Node {
-                            addresses: vec![RawAddr::from(addr)],
+                            addresses: vec![addr],
                         })
                         .collect::<Vec<_>>();
                     let nodes = Nodes {
@@ -239,7 +251,7 @@ impl SubstreamValue {
                     if nodes.items.len() > ANNOUNCE_THRESHOLD {
                         warn!("Nodes number more than {}", ANNOUNCE_THRESHOLD);
                         // TODO: misbehavior
-                        if addr_mgr.misbehave(self.remote_addr.into_inner(), 222) < 0 {
+                        if addr_mgr.misbehave(self.remote_addr.inner().clone(), 222) < 0 {
                             // TODO: more clear error type
                             return Err(io::ErrorKind::Other.into());
                         }
@@ -249,7 +261,7 @@ impl SubstreamValue {
                 } else if self.received_nodes {
                     warn!("already received Nodes(announce=false) message");
                     // TODO: misbehavior
-                    if addr_mgr.misbehave(self.remote_addr.into_inner(), 333) < 0 {
+                    if addr_mgr.misbehave(self.remote_addr.inner().clone(), 333) < 0 {
                         // TODO: more clear error type
                         return Err(io::ErrorKind::Other.into());
                     }
@@ -259,7 +271,7 @@ impl SubstreamValue {
                         nodes.items.len()
                     );
                     // TODO: misbehavior
-                    if addr_mgr.misbehave(self.remote_addr.into_inner(), 444) < 0 {
+                    if addr_mgr.misbehave(self.remote_addr.inner().clone(), 444) < 0 {
                         // TODO: more clear error type
                         return Err(io::ErrorKind::Other.into());
                     }
@@ -289,8 +301,8 @@ impl SubstreamValue {
                         // Add to known address list
                         for node in &nodes.items {
                             for addr in &node.addresses {
-                                trace!("received address: {}", addr.socket_addr());
-                                self.addr_known.insert(*addr);
+                                trace!("received address: {}", addr);
+                                self.addr_known.insert(addr.clone());
                             }
                         }
                         nodes_list.push(nodes);
@@ -311,7 +323,7 @@ impl SubstreamValue {
 }
 
 pub struct Substream {
-    pub remote_addr: SocketAddr,
+    pub remote_addr: Multiaddr,
     pub direction: Direction,
     pub stream: StreamHandle,
     pub listen_port: Option<u16>,
@@ -319,13 +331,13 @@ pub struct Substream {
 
 impl Substream {
     pub fn new(
-        remote_addr: SocketAddr,
+        remote_addr: Multiaddr,
         direction: Direction,
         proto_id: ProtocolId,
         session_id: SessionId,
         receiver: Receiver<Vec<u8>>,
         sender: Sender<ServiceTask>,
-        listens: &[SocketAddr],
+        listens: &[Multiaddr],
     ) -> Substream {
         let stream = StreamHandle {
             data_buf: BytesMut::default(),
@@ -335,13 +347,15 @@ impl Substream {
             sender,
         };
         let listen_port = if direction == Direction::Outbound {
-            let local = remote_addr.ip().is_loopback();
+            let local = multiaddr_to_ip(&remote_addr)
+                .map(|ip| ip.is_loopback())
+                .unwrap_or(false);
 
             listens
                 .iter()
                 .filter_map(|address| {
-                    if local || RawAddr::from(*address).is_reachable() {
-                        Some(address.port())
+                    if local || is_reachable(address) {
+                        multiaddr_port(address)
                     } else {
                         None
                     }
@@ -377,27 +391,33 @@ pub enum Direction {
     Outbound,
 }
 
-#[derive(Eq, PartialEq, Hash, Debug, Clone, Copy)]
+#[derive(Eq, PartialEq, Hash, Debug, Clone)]
 pub(crate) enum RemoteAddress {
     /// Inbound init remote address
-    Init(SocketAddr),
+    Init(Multiaddr),
     /// Outbound init remote address or Inbound listen address
-    Listen(SocketAddr),
+    Listen(Multiaddr),
 }
 
 impl RemoteAddress {
-    pub(crate) fn into_inner(self) -> SocketAddr {
+    pub(crate) fn inner(&self) -> &Multiaddr {
         match self {
             RemoteAddress::Init(addr) | RemoteAddress::Listen(addr) => addr,
         }
     }
 
-    fn into_listen(self, port: u16) -> Self {
-        if let RemoteAddress::Init(mut addr) = self {
-            addr.set_port(port);
+    fn to_listen(&self, port: u16) -> Self {
+        if let RemoteAddress::Init(addr) = self {
+            let addr = addr
+                .iter()
+                .map(|protocol| match protocol {
+                    Protocol::Tcp(_) => Protocol::Tcp(port),
+                    protocol => protocol.clone(),
+                })
+                .collect();
             RemoteAddress::Listen(addr)
         } else {
-            self
+            self.clone()
         }
     }
 }
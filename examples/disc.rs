@@ -4,7 +4,6 @@ use log::{debug, warn};
 use fnv::FnvHashMap;
 use std::{
     collections::HashMap,
-    net::{IpAddr, Ipv4Addr, SocketAddr},
     str,
     time::{Duration, Instant},
 };
@@ -18,13 +17,15 @@ use tokio::timer::{Error, Interval};
 
 use p2p::{
     builder::ServiceBuilder,
+    codec::Codec,
+    multiaddr::Multiaddr,
     service::{Message, ProtocolHandle, ServiceContext, ServiceEvent, ServiceHandle, ServiceTask},
     session::{ProtocolId, ProtocolMeta, SessionId},
     SessionType,
 };
-use secio::PublicKey;
+use secio::{PeerId, PublicKey};
 
-use discovery::{AddressManager, Direction, Discovery, DiscoveryHandle, RawAddr, Substream};
+use discovery::{AddressManager, Direction, Discovery, DiscoveryHandle, Substream};
 
 fn main() {
     env_logger::init();
@@ -36,7 +37,7 @@ fn main() {
             .insert_protocol(protocol)
             .forever(true)
             .build(SHandle {});
-        let _ = service.listen("127.0.0.1:1337".parse().unwrap());
+        let _ = service.listen("/ip4/127.0.0.1/tcp/1337".parse().unwrap());
         tokio::run(service.for_each(|_| Ok(())))
     } else {
         debug!("Starting client ......");
@@ -46,16 +47,16 @@ fn main() {
             .insert_protocol(protocol)
             .forever(true)
             .build(SHandle {})
-            .dial("127.0.0.1:1337".parse().unwrap());
-        let _ = service.listen("127.0.0.1:1338".parse().unwrap());
+            .dial("/ip4/127.0.0.1/tcp/1337".parse().unwrap());
+        let _ = service.listen("/ip4/127.0.0.1/tcp/1338".parse().unwrap());
         tokio::run(service.for_each(|_| Ok(())))
     }
 }
 
 fn create_discovery(start: u16) -> (Discovery<SimpleAddressManager>, DiscoveryHandle) {
-    let addrs: FnvHashMap<RawAddr, i32> = (start..start + 3333)
-        .map(|port| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port))
-        .map(|addr| (RawAddr::from(addr), 100))
+    let addrs: FnvHashMap<Multiaddr, i32> = (start..start + 3333)
+        .map(|port| format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap())
+        .map(|addr| (addr, 100))
         .collect();
     let addr_mgr = SimpleAddressManager { addrs };
     let discovery = Discovery::new(addr_mgr);
@@ -92,12 +93,12 @@ impl DiscoveryProtocol {
     }
 }
 
-impl ProtocolMeta<LengthDelimitedCodec> for DiscoveryProtocol {
+impl ProtocolMeta for DiscoveryProtocol {
     fn id(&self) -> ProtocolId {
         self.id
     }
-    fn codec(&self) -> LengthDelimitedCodec {
-        LengthDelimitedCodec::new()
+    fn codec(&self) -> Box<dyn Codec> {
+        Box::new(LengthDelimitedCodec::new())
     }
     fn handle(&self) -> Option<Box<dyn ProtocolHandle + Send + 'static>> {
         let discovery = self
@@ -167,14 +168,15 @@ impl ProtocolHandle for DiscoveryProtocol {
         &mut self,
         control: &mut ServiceContext,
         session_id: SessionId,
-        address: SocketAddr,
+        address: Multiaddr,
         ty: SessionType,
         _: &Option<PublicKey>,
+        _: Option<&PeerId>,
         _: &str,
     ) {
         self.sessions
             .entry(session_id)
-            .or_insert(SessionData::new(address, ty));
+            .or_insert(SessionData::new(address.clone(), ty));
         debug!(
             "protocol [discovery] open on session [{}], address: [{}], type: [{:?}]",
             session_id, address, ty
@@ -252,12 +254,12 @@ impl ServiceHandle for SHandle {
 #[derive(Clone)]
 struct SessionData {
     ty: SessionType,
-    address: SocketAddr,
+    address: Multiaddr,
     data: Vec<Vec<u8>>,
 }
 
 impl SessionData {
-    fn new(address: SocketAddr, ty: SessionType) -> Self {
+    fn new(address: Multiaddr, ty: SessionType) -> Self {
         SessionData {
             address,
             ty,
@@ -272,25 +274,21 @@ impl SessionData {
 
 #[derive(Default, Clone, Debug)]
 pub struct SimpleAddressManager {
-    pub addrs: FnvHashMap<RawAddr, i32>,
+    pub addrs: FnvHashMap<Multiaddr, i32>,
 }
 
 impl AddressManager for SimpleAddressManager {
-    fn add_new(&mut self, addr: SocketAddr) {
-        self.addrs.entry(RawAddr::from(addr)).or_insert(100);
+    fn add_new(&mut self, addr: Multiaddr) {
+        self.addrs.entry(addr).or_insert(100);
     }
 
-    fn misbehave(&mut self, addr: SocketAddr, _ty: u64) -> i32 {
-        let value = self.addrs.entry(RawAddr::from(addr)).or_insert(100);
+    fn misbehave(&mut self, addr: Multiaddr, _ty: u64) -> i32 {
+        let value = self.addrs.entry(addr).or_insert(100);
         *value -= 20;
         *value
     }
 
-    fn get_random(&mut self, n: usize) -> Vec<SocketAddr> {
-        self.addrs
-            .keys()
-            .take(n)
-            .map(|addr| addr.socket_addr())
-            .collect()
+    fn get_random(&mut self, n: usize) -> Vec<Multiaddr> {
+        self.addrs.keys().take(n).cloned().collect()
     }
 }
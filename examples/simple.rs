@@ -3,15 +3,16 @@ use futures::{oneshot, prelude::*, sync::oneshot::Sender};
 use log::info;
 use p2p::{
     builder::ServiceBuilder,
+    codec::Codec,
+    multiaddr::Multiaddr,
     service::{
         Message, ProtocolHandle, Service, ServiceContext, ServiceEvent, ServiceHandle, ServiceTask,
     },
     session::{ProtocolId, ProtocolMeta, SessionId},
-    PublicKey, SecioKeyPair, SessionType,
+    PeerId, PublicKey, SecioKeyPair, SessionType,
 };
 use std::collections::HashMap;
 use std::{
-    net::SocketAddr,
     str,
     time::{Duration, Instant},
 };
@@ -28,12 +29,12 @@ impl Protocol {
     }
 }
 
-impl ProtocolMeta<LengthDelimitedCodec> for Protocol {
+impl ProtocolMeta for Protocol {
     fn id(&self) -> ProtocolId {
         self.id
     }
-    fn codec(&self) -> LengthDelimitedCodec {
-        LengthDelimitedCodec::new()
+    fn codec(&self) -> Box<dyn Codec> {
+        Box::new(LengthDelimitedCodec::new())
     }
     fn handle(&self) -> Option<Box<dyn ProtocolHandle + Send + 'static>> {
         // All protocol use the same handle.
@@ -78,9 +79,10 @@ impl ProtocolHandle for PHandle {
         &mut self,
         control: &mut ServiceContext,
         session_id: SessionId,
-        address: SocketAddr,
+        address: Multiaddr,
         ty: SessionType,
         _remote_public_key: &Option<PublicKey>,
+        _peer_id: Option<&PeerId>,
         version: &str,
     ) {
         self.connected_session_ids.push(session_id);
@@ -107,6 +109,7 @@ impl ProtocolHandle for PHandle {
                         id: 0,
                         proto_id: 1,
                         data: b"I am a interval message".to_vec(),
+                        ..Default::default()
                     },
                 });
                 if let Ok(Async::Ready(_)) = receiver.poll() {
@@ -173,6 +176,7 @@ impl ServiceHandle for SHandle {
                             id,
                             proto_id: 0,
                             data: b"I am a delayed message".to_vec(),
+                            ..Default::default()
                         },
                     });
                     Ok(())
@@ -196,7 +200,7 @@ fn main() {
     }
 }
 
-fn create_server() -> Service<SHandle, LengthDelimitedCodec> {
+fn create_server() -> Service<SHandle> {
     ServiceBuilder::default()
         .insert_protocol(Protocol::new(0))
         .insert_protocol(Protocol::new(1))
@@ -209,7 +213,7 @@ fn create_server() -> Service<SHandle, LengthDelimitedCodec> {
 /// Proto 2 open failure
 ///
 /// Because server only supports 0,1
-fn create_client() -> Service<SHandle, LengthDelimitedCodec> {
+fn create_client() -> Service<SHandle> {
     ServiceBuilder::default()
         .insert_protocol(Protocol::new(0))
         .insert_protocol(Protocol::new(1))
@@ -220,14 +224,14 @@ fn create_client() -> Service<SHandle, LengthDelimitedCodec> {
 
 fn server() {
     let mut service = create_server();
-    let _ = service.listen("127.0.0.1:1337".parse().unwrap());
+    let _ = service.listen("/ip4/127.0.0.1/tcp/1337".parse().unwrap());
 
     tokio::run(service.for_each(|_| Ok(())))
 }
 
 fn client() {
-    let mut service = create_client().dial("127.0.0.1:1337".parse().unwrap());
-    let _ = service.listen("127.0.0.1:1337".parse().unwrap());
+    let mut service = create_client().dial("/ip4/127.0.0.1/tcp/1337".parse().unwrap());
+    let _ = service.listen("/ip4/127.0.0.1/tcp/1337".parse().unwrap());
 
     tokio::run(service.for_each(|_| Ok(())))
 }
@@ -2,6 +2,8 @@
 
 /// Encryption and decryption stream
 pub mod secure_stream;
+/// Traffic statistics of the streams
+pub mod stats;
 /// Stream handle
 pub mod stream_handle;
 
@@ -9,9 +9,13 @@ use tokio::prelude::{AsyncRead, AsyncWrite};
 use std::cmp::min;
 use std::collections::VecDeque;
 use std::io;
+use std::sync::Arc;
 
 use crate::{
-    codec::{stream_handle::StreamEvent, stream_handle::StreamHandle, Hmac, StreamCipher},
+    codec::{
+        stats::TrafficStats, stream_handle::StreamEvent, stream_handle::StreamHandle, Hmac,
+        StreamCipher,
+    },
     error::SecioError,
 };
 
@@ -38,6 +42,8 @@ pub struct SecureStream<T> {
     event_sender: Sender<StreamEvent>,
     // For receive events from sub streams
     event_receiver: Receiver<StreamEvent>,
+    /// Counters of the encrypted frames
+    stats: Arc<TrafficStats>,
 }
 
 impl<T> SecureStream<T>
@@ -67,6 +73,7 @@ where
             frame_sender: None,
             event_sender,
             event_receiver,
+            stats: Arc::new(TrafficStats::new()),
         }
     }
 
@@ -79,7 +86,11 @@ where
         }
         let (frame_sender, frame_receiver) = mpsc::channel(1024);
         self.frame_sender = Some(frame_sender);
-        Ok(StreamHandle::new(frame_receiver, self.event_sender.clone()))
+        Ok(StreamHandle::new(
+            frame_receiver,
+            self.event_sender.clone(),
+            self.stats.clone(),
+        ))
     }
 
     #[inline]
@@ -102,6 +113,7 @@ where
             StreamEvent::Frame(mut frame) => {
                 debug!("start send data: {:?}", frame);
                 self.encode(&mut frame);
+                self.stats.record_out(frame.len());
                 self.pending.push_back(frame.freeze());
                 self.send_frame()?;
             }
@@ -123,6 +135,7 @@ where
             match self.socket.poll() {
                 Ok(Async::Ready(Some(t))) => {
                     debug!("receive raw data: {:?}", t);
+                    self.stats.record_in(t.len());
                     let data = self.decode(&t)?;
                     debug!("receive data: {:?}", data);
                     self.read_buf.push_back(BytesMut::from(data));
@@ -130,6 +143,7 @@ where
                         while let Some(data) = self.read_buf.pop_front() {
                             if let Err(e) = sender.try_send(StreamEvent::Frame(data)) {
                                 debug!("send error: {}", e);
+                                self.stats.record_dropped();
                             }
                         }
                     }
@@ -0,0 +1,91 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Traffic counters of a stream, shared between the stream and the observers
+#[derive(Debug)]
+pub struct TrafficStats {
+    created: Instant,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    frames_in: AtomicU64,
+    frames_out: AtomicU64,
+    dropped: AtomicU64,
+    /// Milliseconds from creation to the last frame in or out
+    last_activity: AtomicU64,
+}
+
+impl TrafficStats {
+    /// New counters
+    pub fn new() -> Self {
+        TrafficStats {
+            created: Instant::now(),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            frames_in: AtomicU64::new(0),
+            frames_out: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            last_activity: AtomicU64::new(0),
+        }
+    }
+
+    /// Record an inbound frame
+    pub fn record_in(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.frames_in.fetch_add(1, Ordering::Relaxed);
+        self.touch();
+    }
+
+    /// Record an outbound frame
+    pub fn record_out(&self, bytes: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.frames_out.fetch_add(1, Ordering::Relaxed);
+        self.touch();
+    }
+
+    /// Record a frame dropped because the receiver is not ready or gone
+    pub fn record_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn touch(&self) {
+        let elapsed = self.created.elapsed().as_millis() as u64;
+        self.last_activity.fetch_max(elapsed, Ordering::Relaxed);
+    }
+
+    /// Total bytes received
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    /// Total bytes sent
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    /// Number of frames received
+    pub fn frames_in(&self) -> u64 {
+        self.frames_in.load(Ordering::Relaxed)
+    }
+
+    /// Number of frames sent
+    pub fn frames_out(&self) -> u64 {
+        self.frames_out.load(Ordering::Relaxed)
+    }
+
+    /// Number of frames dropped
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Time since the last frame in or out, or since creation if no frame yet
+    pub fn idle(&self) -> Duration {
+        let last = Duration::from_millis(self.last_activity.load(Ordering::Relaxed));
+        self.created.elapsed().checked_sub(last).unwrap_or_default()
+    }
+}
+
+impl Default for TrafficStats {
+    fn default() -> Self {
+        TrafficStats::new()
+    }
+}
@@ -4,6 +4,9 @@ use futures::sync::mpsc::{Receiver, Sender};
 use tokio::prelude::{AsyncRead, AsyncWrite};
 
 use std::io;
+use std::sync::Arc;
+
+use crate::{codec::stats::TrafficStats, handshake::Negotiated};
 
 /// Stream handle
 #[derive(Debug)]
@@ -13,24 +16,69 @@ pub struct StreamHandle {
     frame_receiver: Receiver<StreamEvent>,
 
     event_sender: Sender<StreamEvent>,
+
+    stats: Arc<TrafficStats>,
+
+    negotiated: Option<Negotiated>,
+
+    /// The nonces of both sides of the handshake, the lower one first
+    nonces: Option<Vec<u8>>,
+
+    /// The secure stream is closed, read returns EOF after the buffered data
+    eof: bool,
 }
 
 impl StreamHandle {
     pub(crate) fn new(
         frame_receiver: Receiver<StreamEvent>,
         event_sender: Sender<StreamEvent>,
+        stats: Arc<TrafficStats>,
     ) -> Self {
         StreamHandle {
             frame_receiver,
             event_sender,
             read_buf: BytesMut::default(),
+            stats,
+            negotiated: None,
+            nonces: None,
+            eof: false,
         }
     }
 
+    /// Counters of the encrypted frames of the secure stream
+    pub fn stats(&self) -> &Arc<TrafficStats> {
+        &self.stats
+    }
+
+    /// The algorithms chosen by the handshake, None if the handle is not created by a handshake
+    pub fn negotiated(&self) -> Option<Negotiated> {
+        self.negotiated
+    }
+
+    pub(crate) fn set_negotiated(&mut self, negotiated: Negotiated) {
+        self.negotiated = Some(negotiated);
+    }
+
+    /// The nonces of the handshake, the same on both ends of the connection,
+    /// None if the handle is not created by a handshake
+    pub fn nonces(&self) -> Option<&[u8]> {
+        self.nonces.as_deref()
+    }
+
+    pub(crate) fn set_nonces(&mut self, local: &[u8], remote: &[u8]) {
+        let (lower, higher) = if local < remote {
+            (local, remote)
+        } else {
+            (remote, local)
+        };
+        self.nonces = Some([lower, higher].concat());
+    }
+
     fn handle_event(&mut self, event: StreamEvent) -> Result<(), io::Error> {
         match event {
             StreamEvent::Frame(frame) => self.read_buf.extend_from_slice(&frame),
             StreamEvent::Close => {
+                self.eof = true;
                 let _ = self.shutdown()?;
             }
             _ => (),
@@ -44,6 +92,9 @@ impl StreamHandle {
             match self.frame_receiver.poll() {
                 Ok(Async::Ready(Some(event))) => self.handle_event(event)?,
                 Ok(Async::Ready(None)) => {
+                    if self.eof {
+                        break;
+                    }
                     return Err(io::ErrorKind::BrokenPipe.into());
                 }
                 Ok(Async::NotReady) => break,
@@ -56,11 +107,16 @@ impl StreamHandle {
 
 impl io::Read for StreamHandle {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.recv_frames()?;
+        let result = self.recv_frames();
 
         let n = ::std::cmp::min(buf.len(), self.read_buf.len());
 
         if n == 0 {
+            // The frames received before closing are still readable
+            result?;
+            if self.eof {
+                return Ok(0);
+            }
             return Err(io::ErrorKind::WouldBlock.into());
         }
 
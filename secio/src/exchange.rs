@@ -10,7 +10,9 @@ use crate::error::SecioError;
 /// Possible key agreement algorithms.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum KeyAgreement {
+    /// ECDH on the P-256 curve
     EcdhP256,
+    /// ECDH on the P-384 curve
     EcdhP384,
 }
 
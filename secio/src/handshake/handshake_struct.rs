@@ -5,6 +5,8 @@ use crate::handshake::handshake_generated::p2p::handshake::{
 
 use flatbuffers::{get_root, FlatBufferBuilder};
 
+use crate::PeerId;
+
 #[derive(Clone, Default, PartialEq, Ord, PartialOrd, Eq, Debug)]
 pub struct Propose {
     pub(crate) rand: Vec<u8>,
@@ -112,6 +114,11 @@ pub enum PublicKey {
 }
 
 impl PublicKey {
+    /// Peer id of the public key
+    pub fn peer_id(&self) -> PeerId {
+        PeerId::from_public_key(self)
+    }
+
     /// Get inner data
     pub fn inner_ref(&self) -> &Vec<u8> {
         match self {
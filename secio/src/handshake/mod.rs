@@ -15,6 +15,17 @@ mod handshake_generated;
 pub(crate) mod handshake_struct;
 mod procedure;
 
+/// The algorithms chosen by the handshake
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Negotiated {
+    /// Key agreement of the ephemeral keys
+    pub agreement: KeyAgreement,
+    /// Cipher of the secure stream
+    pub cipher: Cipher,
+    /// Digest of the hmac
+    pub digest: Digest,
+}
+
 /// Config for Secio
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -18,11 +18,11 @@ use crate::{
     codec::{secure_stream::SecureStream, stream_handle::StreamHandle, Hmac},
     error::SecioError,
     exchange,
-    handshake::Config,
     handshake::{
         handshake_context::HandshakeContext,
         handshake_struct::{Exchange, PublicKey},
     },
+    handshake::{Config, Negotiated},
     stream_cipher::ctr_init,
     EphemeralPublicKey, KeyPairInner,
 };
@@ -289,6 +289,13 @@ where
         })
         .and_then(|(mut secure_stream, pub_ephemeral_context)| {
             let mut handle = secure_stream.create_handle().unwrap();
+            let remote = &pub_ephemeral_context.state.remote;
+            handle.set_negotiated(Negotiated {
+                agreement: remote.chosen_exchange,
+                cipher: remote.chosen_cipher,
+                digest: remote.chosen_hash,
+            });
+            handle.set_nonces(&remote.local.nonce, &remote.nonce);
 
             tokio::spawn(
                 secure_stream
@@ -4,7 +4,12 @@
 
 use secp256k1::key::SecretKey;
 
-pub use crate::handshake::handshake_struct::PublicKey;
+pub use crate::{
+    exchange::KeyAgreement,
+    handshake::{handshake_struct::PublicKey, Negotiated},
+    peer_id::{InvalidPeerId, PeerId},
+    stream_cipher::Cipher,
+};
 
 /// Encrypted and decrypted codec implementation, and stream handle
 pub mod codec;
@@ -14,6 +19,8 @@ mod error;
 mod exchange;
 /// Implementation of the handshake process
 pub mod handshake;
+/// Identity of the peers
+mod peer_id;
 /// Encrypted stream
 mod stream_cipher;
 /// Supported algorithms
@@ -53,6 +60,11 @@ impl SecioKeyPair {
         }
     }
 
+    /// Returns the peer id corresponding to this key pair.
+    pub fn peer_id(&self) -> PeerId {
+        self.to_public_key().peer_id()
+    }
+
     /// Returns the public key corresponding to this key pair.
     pub fn to_public_key(&self) -> PublicKey {
         match self.inner {
@@ -0,0 +1,126 @@
+use sha2::{Digest as Sha2Digest, Sha256};
+use std::{error, fmt, str::FromStr};
+
+use crate::PublicKey;
+
+/// Code of sha256 in multihash
+const SHA256_CODE: u8 = 0x12;
+/// Length of the sha256 digest
+const SHA256_SIZE: u8 = 32;
+
+/// Identity of a peer, the sha256 multihash of its encoded public key.
+///
+/// Displayed and parsed in base58
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PeerId {
+    inner: Vec<u8>,
+}
+
+impl PeerId {
+    /// Peer id of the public key
+    pub fn from_public_key(public_key: &PublicKey) -> Self {
+        let mut inner = vec![SHA256_CODE, SHA256_SIZE];
+        inner.extend_from_slice(&Sha256::digest(&public_key.encode()));
+        PeerId { inner }
+    }
+
+    /// Parse from the multihash bytes
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, InvalidPeerId> {
+        if data.len() == SHA256_SIZE as usize + 2
+            && data[0] == SHA256_CODE
+            && data[1] == SHA256_SIZE
+        {
+            Ok(PeerId { inner: data })
+        } else {
+            Err(InvalidPeerId)
+        }
+    }
+
+    /// Parse from the base58 string
+    pub fn from_base58(input: &str) -> Result<Self, InvalidPeerId> {
+        let data = bs58::decode(input).into_vec().map_err(|_| InvalidPeerId)?;
+        PeerId::from_bytes(data)
+    }
+
+    /// The multihash bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.inner
+    }
+
+    /// Encode in base58
+    pub fn to_base58(&self) -> String {
+        bs58::encode(&self.inner).into_string()
+    }
+
+    /// Whether the peer id is derived from the public key
+    pub fn is_public_key(&self, public_key: &PublicKey) -> bool {
+        *self == PeerId::from_public_key(public_key)
+    }
+}
+
+impl From<&PublicKey> for PeerId {
+    fn from(public_key: &PublicKey) -> Self {
+        PeerId::from_public_key(public_key)
+    }
+}
+
+impl From<PublicKey> for PeerId {
+    fn from(public_key: PublicKey) -> Self {
+        PeerId::from_public_key(&public_key)
+    }
+}
+
+impl fmt::Debug for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PeerId({})", self.to_base58())
+    }
+}
+
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_base58())
+    }
+}
+
+impl FromStr for PeerId {
+    type Err = InvalidPeerId;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PeerId::from_base58(s)
+    }
+}
+
+/// The input is not a valid peer id
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidPeerId;
+
+impl fmt::Display for InvalidPeerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid peer id")
+    }
+}
+
+impl error::Error for InvalidPeerId {}
+
+#[cfg(test)]
+mod tests {
+    use super::{InvalidPeerId, PeerId};
+    use crate::SecioKeyPair;
+
+    #[test]
+    fn test_peer_id() {
+        let public_key = SecioKeyPair::secp256k1_generated().to_public_key();
+        let peer_id = public_key.peer_id();
+        assert!(peer_id.is_public_key(&public_key));
+        assert_eq!(peer_id.as_bytes().len(), 34);
+
+        let encoded = peer_id.to_string();
+        assert_eq!(encoded.parse::<PeerId>(), Ok(peer_id.clone()));
+        assert_eq!(PeerId::from_bytes(peer_id.as_bytes().to_vec()), Ok(peer_id));
+
+        let other = SecioKeyPair::secp256k1_generated().to_public_key();
+        assert!(!other.peer_id().is_public_key(&public_key));
+        assert_eq!("0OIl".parse::<PeerId>(), Err(InvalidPeerId));
+        assert_eq!(PeerId::from_bytes(vec![0x12, 0x20, 1]), Err(InvalidPeerId));
+    }
+}
@@ -9,10 +9,13 @@ use ctr::Ctr128;
 use twofish::Twofish;
 
 /// Possible encryption ciphers.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Cipher {
+    /// Aes 128 in ctr mode
     Aes128,
+    /// Aes 256 in ctr mode
     Aes256,
+    /// Twofish in ctr mode
     TwofishCtr,
 }
 
@@ -0,0 +1,162 @@
+use secio::PublicKey;
+use std::time::Instant;
+
+use crate::multiaddr::{multiaddr_to_ip, Multiaddr};
+
+/// The peer to be banned
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BanTarget {
+    /// Ban the ip of the address, if the address has no ip, ban the exact address
+    Address(Multiaddr),
+    /// Ban the public key, checked after secio handshake
+    PublicKey(PublicKey),
+}
+
+impl BanTarget {
+    /// Whether the address is covered by this target
+    pub fn match_address(&self, address: &Multiaddr) -> bool {
+        match self {
+            BanTarget::Address(target) => match multiaddr_to_ip(target) {
+                Some(ip) => multiaddr_to_ip(address) == Some(ip),
+                None => target == address,
+            },
+            BanTarget::PublicKey(_) => false,
+        }
+    }
+
+    /// Whether the public key is covered by this target
+    pub fn match_public_key(&self, public_key: &PublicKey) -> bool {
+        match self {
+            BanTarget::PublicKey(target) => target == public_key,
+            BanTarget::Address(_) => false,
+        }
+    }
+}
+
+impl From<Multiaddr> for BanTarget {
+    fn from(address: Multiaddr) -> Self {
+        BanTarget::Address(address)
+    }
+}
+
+impl From<PublicKey> for BanTarget {
+    fn from(public_key: PublicKey) -> Self {
+        BanTarget::PublicKey(public_key)
+    }
+}
+
+/// A ban entry
+#[derive(Clone, Debug)]
+pub struct Ban {
+    /// Banned peer
+    pub target: BanTarget,
+    /// The ban is lifted at this time, None means permanent
+    pub until: Option<Instant>,
+}
+
+impl Ban {
+    /// Whether the ban is still in effect
+    fn is_active(&self, now: Instant) -> bool {
+        match self.until {
+            Some(until) => until > now,
+            None => true,
+        }
+    }
+}
+
+/// The list of banned peers, expired bans are ignored
+#[derive(Clone, Debug, Default)]
+pub struct BanList {
+    inner: Vec<Ban>,
+}
+
+impl BanList {
+    /// Ban the target until the given time or forever, replace the previous ban of the same target
+    pub(crate) fn insert(&mut self, target: BanTarget, until: Option<Instant>) {
+        self.remove(&target);
+        self.inner.push(Ban { target, until });
+    }
+
+    /// Lift the ban of the target, return false if it's not banned
+    pub(crate) fn remove(&mut self, target: &BanTarget) -> bool {
+        let len = self.inner.len();
+        self.inner.retain(|ban| &ban.target != target);
+        self.inner.len() != len
+    }
+
+    /// Remove the expired bans, return false if nothing is removed
+    pub(crate) fn clear_expired(&mut self, now: Instant) -> bool {
+        let len = self.inner.len();
+        self.inner.retain(|ban| ban.is_active(now));
+        self.inner.len() != len
+    }
+
+    /// Whether the address is banned now
+    pub fn is_address_banned(&self, address: &Multiaddr) -> bool {
+        let now = Instant::now();
+        self.inner
+            .iter()
+            .any(|ban| ban.is_active(now) && ban.target.match_address(address))
+    }
+
+    /// Whether the public key is banned now
+    pub fn is_public_key_banned(&self, public_key: &PublicKey) -> bool {
+        let now = Instant::now();
+        self.inner
+            .iter()
+            .any(|ban| ban.is_active(now) && ban.target.match_public_key(public_key))
+    }
+
+    /// Iterate over the bans
+    pub fn iter(&self) -> impl Iterator<Item = &Ban> {
+        self.inner.iter()
+    }
+
+    /// Number of the bans
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Whether no peer is banned
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BanList, BanTarget};
+    use crate::multiaddr::Multiaddr;
+    use crate::SecioKeyPair;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_ban_match() {
+        let mut list = BanList::default();
+        let now = Instant::now();
+        let address: Multiaddr = "/ip4/1.2.3.4/tcp/1337".parse().unwrap();
+        let key = SecioKeyPair::secp256k1_generated().to_public_key();
+
+        list.insert(address.into(), Some(now + Duration::from_secs(60)));
+        list.insert(
+            "/memory/node".parse::<Multiaddr>().unwrap().into(),
+            Some(now),
+        );
+        list.insert(key.clone().into(), Some(now + Duration::from_secs(60)));
+        // Permanent
+        list.insert("/memory/forever".parse::<Multiaddr>().unwrap().into(), None);
+
+        // Same ip with other port
+        assert!(list.is_address_banned(&"/ip4/1.2.3.4/tcp/80/ws".parse().unwrap()));
+        assert!(!list.is_address_banned(&"/ip4/1.2.3.5/tcp/1337".parse().unwrap()));
+        // Expired
+        assert!(!list.is_address_banned(&"/memory/node".parse().unwrap()));
+        assert!(list.is_public_key_banned(&key));
+        assert!(list.is_address_banned(&"/memory/forever".parse().unwrap()));
+
+        assert!(list.clear_expired(now));
+        assert_eq!(list.len(), 3);
+        assert!(list.remove(&BanTarget::PublicKey(key.clone())));
+        assert!(!list.is_public_key_banned(&key));
+    }
+}
@@ -1,48 +1,84 @@
-use secio::SecioKeyPair;
+use futures::sync::mpsc::{channel, Receiver};
+use secio::{Cipher, Digest, KeyAgreement, SecioKeyPair};
 use std::collections::HashMap;
-use std::marker::PhantomData;
-use std::sync::Arc;
-use std::{error, io};
-use tokio::codec::{Decoder, Encoder};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use crate::{
-    service::{Service, ServiceHandle},
+    channel::{ChannelEvent, ChannelHandle, ChannelSender},
+    metrics::Metrics,
+    multiaddr::Multiaddr,
+    service::{ConnectionLimits, Service, ServiceHandle, SessionConfig},
     session::ProtocolMeta,
+    transport::{TcpTransport, Transport, WsTransport},
 };
 
+#[cfg(unix)]
+use crate::transport::UnixTransport;
+
+/// The default capacity of the event channel
+const CHANNEL_SIZE: usize = 1024;
+
 /// Builder for Service
-pub struct ServiceBuilder<T, U> {
-    inner: HashMap<String, Box<dyn ProtocolMeta<U> + Send + Sync>>,
+pub struct ServiceBuilder {
+    inner: HashMap<String, Box<dyn ProtocolMeta + Send + Sync>>,
     key_pair: Option<SecioKeyPair>,
     forever: bool,
-    phantom: PhantomData<T>,
+    transports: Vec<Box<dyn Transport + Send>>,
+    limits: ConnectionLimits,
+    config: SessionConfig,
+    persistent_peers: Vec<Multiaddr>,
+    channel_size: usize,
 }
 
-impl<T, U> ServiceBuilder<T, U>
-where
-    T: ProtocolMeta<U> + Send + Sync + 'static,
-    U: Decoder<Item = bytes::BytesMut> + Encoder<Item = bytes::Bytes> + Send + 'static,
-    <U as Decoder>::Error: error::Error + Into<io::Error>,
-    <U as Encoder>::Error: error::Error + Into<io::Error>,
-{
+impl ServiceBuilder {
     /// New a default empty builder
     pub fn new() -> Self {
         Default::default()
     }
 
     /// Combine the configuration of this builder with service handle to create a Service.
-    pub fn build<H>(self, handle: H) -> Service<H, U>
+    pub fn build<H>(self, handle: H) -> Service<H>
     where
         H: ServiceHandle,
     {
-        Service::new(Arc::new(self.inner), handle, self.key_pair, self.forever)
+        let mut service = Service::new(
+            self.inner,
+            handle,
+            self.key_pair,
+            self.forever,
+            self.transports,
+            self.limits,
+            self.config,
+        );
+        for address in self.persistent_peers {
+            service.add_persistent_peer(address);
+        }
+        service
+    }
+
+    /// Create a Service emitting all events to the returned channel instead of calling the handles.
+    ///
+    /// The handles of the protocols are still called, use `Service::control` to send commands.
+    /// The channel is bounded, when it's full the messages are dropped and counted in the metrics,
+    /// the other events wait for room in the channel.
+    pub fn build_channel(self) -> (Service<ChannelHandle>, Receiver<ChannelEvent>) {
+        let (sender, receiver) = channel(self.channel_size);
+        let metrics = Arc::new(Metrics::default());
+        let sender = Arc::new(Mutex::new(ChannelSender::new(sender, metrics.clone())));
+        let mut service = self.build(ChannelHandle::new(sender.clone()));
+        service.event_sender(sender, metrics);
+        (service, receiver)
     }
 
-    /// Insert a custom protocol
-    pub fn insert_protocol(mut self, protocol: T) -> Self {
+    /// Insert a custom protocol, the protocols can be of different types
+    pub fn insert_protocol<T>(mut self, protocol: T) -> Self
+    where
+        T: ProtocolMeta + Send + Sync + 'static,
+    {
         self.inner.insert(
             protocol.name(),
-            Box::new(protocol) as Box<dyn ProtocolMeta<_> + Send + Sync>,
+            Box::new(protocol) as Box<dyn ProtocolMeta + Send + Sync>,
         );
         self
     }
@@ -62,25 +98,118 @@ where
         self
     }
 
+    /// Add a transport used by service to listen and dial, default are tcp, websocket and unix domain socket.
+    ///
+    /// Transports added later take precedence over the earlier ones for the same address
+    pub fn transport<Tr>(mut self, transport: Tr) -> Self
+    where
+        Tr: Transport + Send + 'static,
+    {
+        self.transports.insert(0, Box::new(transport));
+        self
+    }
+
+    /// Max number of sessions, including the sessions in handshake, default is unlimited
+    pub fn max_sessions(mut self, max: usize) -> Self {
+        self.limits.max_sessions = Some(max);
+        self
+    }
+
+    /// Max number of inbound sessions, default is unlimited
+    pub fn max_inbound_sessions(mut self, max: usize) -> Self {
+        self.limits.max_inbound = Some(max);
+        self
+    }
+
+    /// Max number of outbound sessions, default is unlimited
+    pub fn max_outbound_sessions(mut self, max: usize) -> Self {
+        self.limits.max_outbound = Some(max);
+        self
+    }
+
+    /// Max number of sessions with the same remote ip, default is unlimited
+    pub fn max_sessions_per_ip(mut self, max: usize) -> Self {
+        self.limits.max_per_ip = Some(max);
+        self
+    }
+
+    /// Yamux config of the sessions, such as the window size and the keepalive
+    pub fn yamux_config(mut self, config: yamux::Config) -> Self {
+        self.config.yamux = config;
+        self
+    }
+
+    /// Key agreements proposed in the secio handshake, default are all supported
+    pub fn key_agreements(mut self, key_agreements: Vec<KeyAgreement>) -> Self {
+        self.config.key_agreements = Some(key_agreements);
+        self
+    }
+
+    /// Ciphers proposed in the secio handshake, default are all supported
+    pub fn ciphers(mut self, ciphers: Vec<Cipher>) -> Self {
+        self.config.ciphers = Some(ciphers);
+        self
+    }
+
+    /// Digests proposed in the secio handshake, default are all supported
+    pub fn digests(mut self, digests: Vec<Digest>) -> Self {
+        self.config.digests = Some(digests);
+        self
+    }
+
+    /// Timeout of the secio handshake, default is 10 seconds
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.config.handshake_timeout = timeout;
+        self
+    }
+
+    /// Timeout of a protocol negotiation, default is 10 seconds
+    pub fn protocol_timeout(mut self, timeout: Duration) -> Self {
+        self.config.protocol_timeout = timeout;
+        self
+    }
+
+    /// Timeout of a graceful shutdown, the sessions still open after it are closed forcibly,
+    /// default is 10 seconds
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.config.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Add a persistent peer, service keeps connected to it and redials with backoff
+    pub fn persistent_peer(mut self, address: Multiaddr) -> Self {
+        self.persistent_peers.push(address);
+        self
+    }
+
+    /// Capacity of the event channel of `build_channel`, default is 1024
+    pub fn channel_size(mut self, size: usize) -> Self {
+        self.channel_size = size;
+        self
+    }
+
     /// Clear all protocols
     pub fn clear(&mut self) {
         self.inner.clear();
     }
 }
 
-impl<T, U> Default for ServiceBuilder<T, U>
-where
-    T: ProtocolMeta<U> + Send + Sync + 'static,
-    U: Decoder<Item = bytes::BytesMut> + Encoder<Item = bytes::Bytes> + Send + 'static,
-    <U as Decoder>::Error: error::Error + Into<io::Error>,
-    <U as Encoder>::Error: error::Error + Into<io::Error>,
-{
+impl Default for ServiceBuilder {
     fn default() -> Self {
         ServiceBuilder {
             inner: HashMap::new(),
             key_pair: None,
             forever: false,
-            phantom: PhantomData,
+            transports: vec![
+                Box::new(TcpTransport),
+                Box::new(WsTransport),
+                #[cfg(unix)]
+                Box::new(UnixTransport),
+            ],
+            limits: ConnectionLimits::default(),
+            config: SessionConfig::default(),
+            persistent_peers: Vec::new(),
+            channel_size: CHANNEL_SIZE,
         }
     }
 }
@@ -0,0 +1,363 @@
+use futures::{prelude::*, sync::mpsc};
+use secio::{PeerId, PublicKey};
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use yamux::session::SessionType;
+
+use crate::{
+    ban::BanTarget,
+    metrics::Metrics,
+    multiaddr::Multiaddr,
+    service::{DialOptions, Message, ServiceContext, ServiceEvent, ServiceHandle, ServiceTask},
+    session::{ProtocolId, SessionId},
+};
+
+/// Event emitted by the Service in channel mode
+#[derive(Debug)]
+pub enum ChannelEvent {
+    /// Session establishment and disconnection events, the same as `ServiceHandle::handle_event`
+    Service(ServiceEvent),
+    /// Runtime errors, the same as `ServiceHandle::handle_error`
+    Error(ServiceEvent),
+    /// A protocol is opened
+    ProtocolOpen {
+        /// Session id
+        id: SessionId,
+        /// Protocol id
+        proto_id: ProtocolId,
+        /// Remote address
+        address: Multiaddr,
+        /// Outbound or Inbound
+        ty: SessionType,
+        /// Remote public key
+        public_key: Option<PublicKey>,
+        /// Remote peer id
+        peer_id: Option<PeerId>,
+        /// Negotiated protocol version
+        version: String,
+    },
+    /// A protocol is closed, also sent before reopening it when both sides opened it
+    /// at the same time, see `ProtocolHandle`
+    ProtocolClose {
+        /// Session id
+        id: SessionId,
+        /// Protocol id
+        proto_id: ProtocolId,
+    },
+    /// A protocol message is received
+    Message(Message),
+}
+
+/// The event channel shared by the channel handle and the service.
+///
+/// Messages are dropped when the channel is full, the other events wait in a queue
+/// and are sent when the channel has room, in order.
+pub(crate) struct ChannelSender {
+    sender: mpsc::Sender<ChannelEvent>,
+    pending: VecDeque<ChannelEvent>,
+    metrics: Arc<Metrics>,
+}
+
+impl ChannelSender {
+    pub(crate) fn new(sender: mpsc::Sender<ChannelEvent>, metrics: Arc<Metrics>) -> Self {
+        ChannelSender {
+            sender,
+            pending: VecDeque::new(),
+            metrics,
+        }
+    }
+
+    /// Send the event, must be called in the service task to be woken up
+    /// when the channel has room again
+    pub(crate) fn send(&mut self, event: ChannelEvent) {
+        if let ChannelEvent::Message(_) = event {
+            // Keep the order, a message can't overtake the queued events
+            if !self.pending.is_empty() || self.sender.try_send(event).is_err() {
+                self.metrics.event_dropped();
+            }
+            return;
+        }
+        self.pending.push_back(event);
+        self.flush();
+    }
+
+    /// Send the queued events until the channel is full
+    pub(crate) fn flush(&mut self) {
+        while !self.pending.is_empty() {
+            match self.sender.poll_ready() {
+                Ok(Async::Ready(())) => {
+                    let event = self.pending.pop_front().unwrap();
+                    let _ = self.sender.try_send(event);
+                }
+                Ok(Async::NotReady) => break,
+                // Nobody receives the events
+                Err(_) => self.pending.clear(),
+            }
+        }
+    }
+}
+
+/// Service handle forwarding all events to the channel
+pub struct ChannelHandle {
+    sender: Arc<Mutex<ChannelSender>>,
+}
+
+impl ChannelHandle {
+    pub(crate) fn new(sender: Arc<Mutex<ChannelSender>>) -> Self {
+        ChannelHandle { sender }
+    }
+
+    fn send(&mut self, event: ChannelEvent) {
+        self.sender.lock().unwrap().send(event);
+    }
+}
+
+impl ServiceHandle for ChannelHandle {
+    fn handle_error(&mut self, _control: &mut ServiceContext, error: ServiceEvent) {
+        self.send(ChannelEvent::Error(error));
+    }
+
+    fn handle_event(&mut self, _control: &mut ServiceContext, event: ServiceEvent) {
+        self.send(ChannelEvent::Service(event));
+    }
+}
+
+/// Cloneable handle to send commands to the Service from any task
+#[derive(Clone)]
+pub struct ServiceControl {
+    service_task_sender: mpsc::Sender<ServiceTask>,
+}
+
+impl ServiceControl {
+    pub(crate) fn new(service_task_sender: mpsc::Sender<ServiceTask>) -> Self {
+        ServiceControl {
+            service_task_sender,
+        }
+    }
+
+    /// Initiate a connection request to address
+    pub fn dial(&mut self, address: Multiaddr) -> Result<(), io::Error> {
+        self.dial_with(address, DialOptions::default())
+    }
+
+    /// Initiate a connection request to address with options
+    pub fn dial_with(&mut self, address: Multiaddr, options: DialOptions) -> Result<(), io::Error> {
+        self.send(ServiceTask::Dial { address, options })
+    }
+
+    /// Disconnect a connection
+    pub fn disconnect(&mut self, id: SessionId) -> Result<(), io::Error> {
+        self.send(ServiceTask::Disconnect { id })
+    }
+
+    /// Disconnect the session with the peer
+    pub fn disconnect_peer(&mut self, peer_id: PeerId) -> Result<(), io::Error> {
+        self.send(ServiceTask::DisconnectPeer { peer_id })
+    }
+
+    /// Open a protocol of the session if it's not open
+    pub fn open_protocol(&mut self, id: SessionId, proto_id: ProtocolId) -> Result<(), io::Error> {
+        self.send(ServiceTask::ProtocolOpen { id, proto_id })
+    }
+
+    /// Close a protocol of the session, the session is kept
+    pub fn close_protocol(&mut self, id: SessionId, proto_id: ProtocolId) -> Result<(), io::Error> {
+        self.send(ServiceTask::ProtocolClose { id, proto_id })
+    }
+
+    /// Send message to the sessions, None means broadcast
+    pub fn send_message(
+        &mut self,
+        ids: Option<Vec<SessionId>>,
+        message: Message,
+    ) -> Result<(), io::Error> {
+        self.send(ServiceTask::ProtocolMessage { ids, message })
+    }
+
+    /// Send message to the session with the peer, the session id of the message is ignored.
+    ///
+    /// `ServiceEvent::MessageDropped` with the peer id is reported if the peer is not connected
+    pub fn send_message_to_peer(
+        &mut self,
+        peer_id: PeerId,
+        message: Message,
+    ) -> Result<(), io::Error> {
+        self.send(ServiceTask::PeerMessage { peer_id, message })
+    }
+
+    /// Ban an address or a public key for a duration, the sessions with it will be closed
+    pub fn ban<B: Into<BanTarget>>(
+        &mut self,
+        target: B,
+        duration: Duration,
+    ) -> Result<(), io::Error> {
+        self.send(ServiceTask::Ban {
+            target: target.into(),
+            duration,
+        })
+    }
+
+    /// Lift the ban of an address or a public key
+    pub fn unban<B: Into<BanTarget>>(&mut self, target: B) -> Result<(), io::Error> {
+        self.send(ServiceTask::Unban {
+            target: target.into(),
+        })
+    }
+
+    /// Keep a connection to the address, redial it after dial failure or session close
+    pub fn add_persistent_peer(&mut self, address: Multiaddr) -> Result<(), io::Error> {
+        self.send(ServiceTask::AddPersistentPeer { address })
+    }
+
+    /// Stop redialing the address, the current session is kept
+    pub fn remove_persistent_peer(&mut self, address: Multiaddr) -> Result<(), io::Error> {
+        self.send(ServiceTask::RemovePersistentPeer { address })
+    }
+
+    /// Shutdown service gracefully, the service stream ends after all sessions are closed
+    pub fn shutdown(&mut self) -> Result<(), io::Error> {
+        self.send(ServiceTask::Shutdown)
+    }
+
+    /// Send the task, `WouldBlock` if the channel is full, `BrokenPipe` if the service is stopped
+    pub fn send(&mut self, task: ServiceTask) -> Result<(), io::Error> {
+        self.service_task_sender.try_send(task).map_err(|err| {
+            if err.is_full() {
+                io::ErrorKind::WouldBlock.into()
+            } else {
+                io::ErrorKind::BrokenPipe.into()
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChannelEvent;
+    use crate::{
+        service::{
+            tests::{builder, connect_channels},
+            Message, ServiceEvent,
+        },
+        transport::MemoryTransport,
+    };
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_channel_events() {
+        let transport = MemoryTransport::new();
+        let (mut server, mut client) = connect_channels(builder(&transport), builder(&transport));
+
+        match client.events.next() {
+            Some(Ok(ChannelEvent::Service(ServiceEvent::SessionOpen { .. }))) => (),
+            event => panic!("unexpected event: {:?}", event),
+        }
+        let id = match client.events.next() {
+            Some(Ok(ChannelEvent::ProtocolOpen { id, proto_id, .. })) => {
+                assert_eq!(proto_id, 1);
+                id
+            }
+            event => panic!("unexpected event: {:?}", event),
+        };
+        client
+            .control
+            .send_message(
+                Some(vec![id]),
+                Message {
+                    id,
+                    proto_id: 1,
+                    data: b"hello".to_vec(),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        let message = server
+            .events
+            .by_ref()
+            .filter_map(|event| match event {
+                Ok(ChannelEvent::Message(message)) => Some(message),
+                _ => None,
+            })
+            .next()
+            .unwrap();
+        assert_eq!(message.proto_id, 1);
+        assert_eq!(message.data, b"hello".to_vec());
+
+        client.control.disconnect(id).unwrap();
+        let closed = client.events.any(|event| match event {
+            Ok(ChannelEvent::ProtocolClose { id: closed, .. }) => closed == id,
+            _ => false,
+        });
+        assert!(closed);
+    }
+
+    #[test]
+    fn test_channel_full() {
+        let transport = MemoryTransport::new();
+        let (server, mut client) =
+            connect_channels(builder(&transport).channel_size(1), builder(&transport));
+
+        let id = client
+            .events
+            .by_ref()
+            .find_map(|event| match event {
+                Ok(ChannelEvent::ProtocolOpen { id, .. }) => Some(id),
+                _ => None,
+            })
+            .unwrap();
+        for _ in 0..10 {
+            let message = Message {
+                id,
+                proto_id: 1,
+                data: b"hello".to_vec(),
+                ..Default::default()
+            };
+            client
+                .control
+                .send_message(Some(vec![id]), message)
+                .unwrap();
+        }
+
+        // Nobody reads the server events
+        let dropped = (0..100).any(|_| {
+            thread::sleep(Duration::from_millis(100));
+            !server
+                .metrics
+                .render()
+                .contains("p2p_channel_events_dropped_total 0\n")
+        });
+        assert!(dropped);
+
+        // Only the messages are dropped
+        client.control.disconnect(id).unwrap();
+        let mut events = Vec::new();
+        for event in server.events {
+            match event {
+                Ok(ChannelEvent::Service(ServiceEvent::SessionOpen { .. })) => {
+                    events.push("session open")
+                }
+                Ok(ChannelEvent::ProtocolOpen { .. }) => events.push("protocol open"),
+                Ok(ChannelEvent::ProtocolClose { .. }) => events.push("protocol close"),
+                Ok(ChannelEvent::Service(ServiceEvent::SessionClose { .. })) => {
+                    events.push("session close");
+                    break;
+                }
+                _ => (),
+            }
+        }
+        assert_eq!(
+            events,
+            vec![
+                "session open",
+                "protocol open",
+                "protocol close",
+                "session close"
+            ]
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use bytes::{Bytes, BytesMut};
+use std::{error, io};
+use tokio::codec::{Decoder, Encoder};
+
+/// Codec of a protocol with the errors converted to `io::Error`,
+/// so that the protocols of a service can use different codecs.
+///
+/// It's implemented for every codec of `Bytes`, such as `LengthDelimitedCodec` by tokio
+pub trait Codec: Send {
+    /// Decode a frame from the buffer
+    fn decode_frame(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, io::Error>;
+    /// Decode a frame from the rest of the buffer after the stream is closed
+    fn decode_frame_eof(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, io::Error>;
+    /// Encode a frame to the buffer
+    fn encode_frame(&mut self, item: Bytes, dst: &mut BytesMut) -> Result<(), io::Error>;
+}
+
+impl<U> Codec for U
+where
+    U: Decoder<Item = BytesMut> + Encoder<Item = Bytes> + Send,
+    <U as Decoder>::Error: error::Error + Into<io::Error>,
+    <U as Encoder>::Error: error::Error + Into<io::Error>,
+{
+    fn decode_frame(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, io::Error> {
+        Decoder::decode(self, src).map_err(Into::into)
+    }
+
+    fn decode_frame_eof(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, io::Error> {
+        Decoder::decode_eof(self, src).map_err(Into::into)
+    }
+
+    fn encode_frame(&mut self, item: Bytes, dst: &mut BytesMut) -> Result<(), io::Error> {
+        Encoder::encode(self, item, dst).map_err(Into::into)
+    }
+}
+
+impl Decoder for Box<dyn Codec> {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        (**self).decode_frame(src)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        (**self).decode_frame_eof(src)
+    }
+}
+
+impl Encoder for Box<dyn Codec> {
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        (**self).encode_frame(item, dst)
+    }
+}
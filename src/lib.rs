@@ -4,17 +4,34 @@
 
 #![deny(missing_docs)]
 
+/// Ban list of peers
+pub mod ban;
 /// Some gadgets that help create a service
 pub mod builder;
+/// Channel based event stream and control handle, an alternative of the handle callbacks
+pub mod channel;
+/// Type-erased codecs of the protocols
+pub mod codec;
+/// Prometheus metrics of the network internals
+pub mod metrics;
+/// Composable address of the underlying connections
+pub mod multiaddr;
+/// Request/response helper over the protocols
+pub mod request;
 /// An abstraction of p2p service
 pub mod service;
 /// Wrapper for real data streams
 pub mod session;
 /// Each custom protocol in a session corresponds to a sub stream
 pub mod substream;
+/// The carrier of the underlying connections
+pub mod transport;
 /// Re-pub some useful structures in secio
-pub use secio::{PublicKey, SecioKeyPair};
+pub use secio::{
+    codec::stats::TrafficStats, Cipher, Digest, KeyAgreement, Negotiated, PeerId, PublicKey,
+    SecioKeyPair,
+};
 /// Re-pub some useful structures in yamux
-pub use yamux::{session::SessionType, Session};
+pub use yamux::{session::SessionType, Config as YamuxConfig, Priority, Session};
 /// Protocol select
 pub mod protocol_select;
@@ -0,0 +1,264 @@
+use futures::prelude::*;
+use log::{debug, warn};
+use secio::codec::stats::TrafficStats;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::{io, net::SocketAddr};
+use tokio::net::TcpListener;
+
+/// Counters of the network internals, shared by the service and its sessions
+#[derive(Debug, Default)]
+pub struct Metrics {
+    sessions: AtomicU64,
+    sessions_opened: AtomicU64,
+    sessions_closed: AtomicU64,
+    handshake_successes: AtomicU64,
+    handshake_failures: AtomicU64,
+    dial_errors: AtomicU64,
+    protocol_opens: AtomicU64,
+    protocol_closes: AtomicU64,
+    window_stalls: AtomicU64,
+    channel_overflows: AtomicU64,
+    events_dropped: AtomicU64,
+    /// Protocol messages of all the sessions
+    traffic: TrafficStats,
+}
+
+impl Metrics {
+    pub(crate) fn session_opened(&self) {
+        self.sessions.fetch_add(1, Ordering::Relaxed);
+        self.sessions_opened.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn session_closed(&self) {
+        self.sessions.fetch_sub(1, Ordering::Relaxed);
+        self.sessions_closed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn handshake_finished(&self, success: bool) {
+        if success {
+            self.handshake_successes.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.handshake_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn dial_error(&self) {
+        self.dial_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn protocol_opened(&self) {
+        self.protocol_opens.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn protocol_closed(&self) {
+        self.protocol_closes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A protocol stream can't write because the yamux send window is exhausted
+    pub(crate) fn window_stall(&self) {
+        self.window_stalls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// An event is dropped because the channel is full
+    pub(crate) fn channel_overflow(&self) {
+        self.channel_overflows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A message of `build_channel` is dropped because the consumer is slow
+    pub(crate) fn event_dropped(&self) {
+        self.events_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn traffic(&self) -> &TrafficStats {
+        &self.traffic
+    }
+
+    /// Render the counters in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let metrics = [
+            (
+                "sessions",
+                "gauge",
+                "Number of open sessions",
+                load(&self.sessions),
+            ),
+            (
+                "sessions_opened_total",
+                "counter",
+                "Sessions opened",
+                load(&self.sessions_opened),
+            ),
+            (
+                "sessions_closed_total",
+                "counter",
+                "Sessions closed",
+                load(&self.sessions_closed),
+            ),
+            (
+                "handshake_successes_total",
+                "counter",
+                "Secio handshakes succeeded",
+                load(&self.handshake_successes),
+            ),
+            (
+                "handshake_failures_total",
+                "counter",
+                "Secio handshakes failed",
+                load(&self.handshake_failures),
+            ),
+            (
+                "dial_errors_total",
+                "counter",
+                "Outbound connections failed",
+                load(&self.dial_errors),
+            ),
+            (
+                "protocol_opens_total",
+                "counter",
+                "Protocol streams opened",
+                load(&self.protocol_opens),
+            ),
+            (
+                "protocol_closes_total",
+                "counter",
+                "Protocol streams closed",
+                load(&self.protocol_closes),
+            ),
+            (
+                "bytes_in_total",
+                "counter",
+                "Bytes of the received protocol messages",
+                self.traffic.bytes_in(),
+            ),
+            (
+                "bytes_out_total",
+                "counter",
+                "Bytes of the sent protocol messages",
+                self.traffic.bytes_out(),
+            ),
+            (
+                "messages_in_total",
+                "counter",
+                "Received protocol messages",
+                self.traffic.frames_in(),
+            ),
+            (
+                "messages_out_total",
+                "counter",
+                "Sent protocol messages",
+                self.traffic.frames_out(),
+            ),
+            (
+                "messages_dropped_total",
+                "counter",
+                "Protocol messages dropped",
+                self.traffic.dropped(),
+            ),
+            (
+                "yamux_window_stalls_total",
+                "counter",
+                "Protocol streams blocked by the yamux send window",
+                load(&self.window_stalls),
+            ),
+            (
+                "channel_overflows_total",
+                "counter",
+                "Events dropped because of full channels",
+                load(&self.channel_overflows),
+            ),
+            (
+                "channel_events_dropped_total",
+                "counter",
+                "Messages dropped because the event channel is full",
+                load(&self.events_dropped),
+            ),
+        ];
+
+        let mut output = String::new();
+        for (name, ty, help, value) in metrics.iter() {
+            let _ = writeln!(output, "# HELP p2p_{} {}", name, help);
+            let _ = writeln!(output, "# TYPE p2p_{} {}", name, ty);
+            let _ = writeln!(output, "p2p_{} {}", name, value);
+        }
+        output
+    }
+}
+
+/// Serve the rendered metrics over HTTP on the address, any path is answered with the metrics.
+///
+/// Spawn the returned future to a tokio runtime
+pub fn serve(
+    metrics: Arc<Metrics>,
+    address: &SocketAddr,
+) -> Result<impl Future<Item = (), Error = ()>, io::Error> {
+    let listener = TcpListener::bind(address)?;
+    let task = listener
+        .incoming()
+        .map_err(|err| warn!("metrics listener error: {:?}", err))
+        .for_each(move |socket| {
+            let metrics = metrics.clone();
+            // The request is ignored, only wait for it before responding
+            let response = tokio::io::read(socket, vec![0; 1024])
+                .and_then(move |(socket, _, _)| {
+                    let body = metrics.render();
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    tokio::io::write_all(socket, response)
+                })
+                .and_then(|(socket, _)| tokio::io::shutdown(socket))
+                .map(|_| ())
+                .map_err(|err| debug!("metrics request error: {:?}", err));
+            tokio::spawn(response);
+            Ok(())
+        });
+    Ok(task)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::serve;
+    use crate::{
+        channel::ChannelEvent,
+        service::tests::{builder, connect_channels},
+        transport::MemoryTransport,
+    };
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    #[test]
+    fn test_metrics() {
+        let transport = MemoryTransport::new();
+        let (_server, mut client) = connect_channels(builder(&transport), builder(&transport));
+        let metrics = client.metrics;
+
+        let opened = client
+            .events
+            .by_ref()
+            .any(|event| matches!(event, Ok(ChannelEvent::ProtocolOpen { .. })));
+        assert!(opened);
+        let rendered = metrics.render();
+        assert!(rendered.contains("# TYPE p2p_sessions gauge\np2p_sessions 1\n"));
+        assert!(rendered.contains("p2p_handshake_successes_total 1\n"));
+        assert!(rendered.contains("p2p_protocol_opens_total 1\n"));
+
+        let listen = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let task = serve(metrics, &listen).unwrap();
+        thread::spawn(|| tokio::run(task));
+        let mut stream = TcpStream::connect(listen).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&rendered));
+    }
+}
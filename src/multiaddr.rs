@@ -0,0 +1,503 @@
+use std::{
+    error, fmt,
+    iter::FromIterator,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    slice,
+    str::FromStr,
+};
+
+const IP4: u64 = 4;
+const TCP: u64 = 6;
+const IP6: u64 = 41;
+const DNS4: u64 = 54;
+const DNS6: u64 = 55;
+const UNIX: u64 = 400;
+const P2P: u64 = 421;
+const WS: u64 = 477;
+const MEMORY: u64 = 777;
+
+/// Error when parsing or decoding a multiaddr
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The string form doesn't start with `/`
+    InvalidMultiaddr,
+    /// Unknown protocol name in the string form
+    UnknownProtocol(String),
+    /// Unknown protocol code in the binary form
+    UnknownProtocolCode(u64),
+    /// The protocol requires a value but it's missing
+    MissingValue(&'static str),
+    /// The value of the protocol can't be parsed
+    InvalidValue(&'static str),
+    /// The binary form ends in the middle of a protocol
+    DataTooShort,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidMultiaddr => write!(f, "multiaddr must start with '/'"),
+            Error::UnknownProtocol(name) => write!(f, "unknown protocol: {}", name),
+            Error::UnknownProtocolCode(code) => write!(f, "unknown protocol code: {}", code),
+            Error::MissingValue(name) => write!(f, "missing value of protocol {}", name),
+            Error::InvalidValue(name) => write!(f, "invalid value of protocol {}", name),
+            Error::DataTooShort => write!(f, "multiaddr data too short"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// A single protocol of a multiaddr
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    /// Ipv4 address
+    Ip4(Ipv4Addr),
+    /// Ipv6 address
+    Ip6(Ipv6Addr),
+    /// Domain name, resolved to ipv4 address
+    Dns4(String),
+    /// Domain name, resolved to ipv6 address
+    Dns6(String),
+    /// Tcp port
+    Tcp(u16),
+    /// Unix domain socket path, it's the last part of the string form
+    Unix(String),
+    /// Named virtual address of memory transport
+    Memory(String),
+    /// WebSocket over the previous protocols
+    Ws,
+    /// Peer identity, base58 in the string form
+    P2p(Vec<u8>),
+}
+
+impl Protocol {
+    /// Name in the string form
+    pub fn name(&self) -> &'static str {
+        match self {
+            Protocol::Ip4(_) => "ip4",
+            Protocol::Ip6(_) => "ip6",
+            Protocol::Dns4(_) => "dns4",
+            Protocol::Dns6(_) => "dns6",
+            Protocol::Tcp(_) => "tcp",
+            Protocol::Unix(_) => "unix",
+            Protocol::Memory(_) => "memory",
+            Protocol::Ws => "ws",
+            Protocol::P2p(_) => "p2p",
+        }
+    }
+
+    /// Code in the binary form
+    pub fn code(&self) -> u64 {
+        match self {
+            Protocol::Ip4(_) => IP4,
+            Protocol::Ip6(_) => IP6,
+            Protocol::Dns4(_) => DNS4,
+            Protocol::Dns6(_) => DNS6,
+            Protocol::Tcp(_) => TCP,
+            Protocol::Unix(_) => UNIX,
+            Protocol::Memory(_) => MEMORY,
+            Protocol::Ws => WS,
+            Protocol::P2p(_) => P2P,
+        }
+    }
+
+    /// Parse one protocol from the string form, `parts` is the rest of the segments
+    fn from_str_parts<'a, I>(name: &str, parts: &mut I) -> Result<Self, Error>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        fn value<'a, I: Iterator<Item = &'a str>>(
+            name: &'static str,
+            parts: &mut I,
+        ) -> Result<&'a str, Error> {
+            parts
+                .next()
+                .filter(|part| !part.is_empty())
+                .ok_or(Error::MissingValue(name))
+        }
+
+        match name {
+            "ip4" => value("ip4", parts)?
+                .parse()
+                .map(Protocol::Ip4)
+                .map_err(|_| Error::InvalidValue("ip4")),
+            "ip6" => value("ip6", parts)?
+                .parse()
+                .map(Protocol::Ip6)
+                .map_err(|_| Error::InvalidValue("ip6")),
+            "dns4" => value("dns4", parts).map(|name| Protocol::Dns4(name.to_owned())),
+            "dns6" => value("dns6", parts).map(|name| Protocol::Dns6(name.to_owned())),
+            "tcp" => value("tcp", parts)?
+                .parse()
+                .map(Protocol::Tcp)
+                .map_err(|_| Error::InvalidValue("tcp")),
+            "unix" => {
+                let path = parts.collect::<Vec<_>>().join("/");
+                if path.is_empty() {
+                    Err(Error::MissingValue("unix"))
+                } else {
+                    Ok(Protocol::Unix(format!("/{}", path)))
+                }
+            }
+            "memory" => value("memory", parts).map(|name| Protocol::Memory(name.to_owned())),
+            "ws" => Ok(Protocol::Ws),
+            "p2p" => bs58::decode(value("p2p", parts)?)
+                .into_vec()
+                .map(Protocol::P2p)
+                .map_err(|_| Error::InvalidValue("p2p")),
+            name => Err(Error::UnknownProtocol(name.to_owned())),
+        }
+    }
+
+    /// Append the binary form to buf
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        write_varint(buf, self.code());
+        match self {
+            Protocol::Ip4(ip) => buf.extend_from_slice(&ip.octets()),
+            Protocol::Ip6(ip) => buf.extend_from_slice(&ip.octets()),
+            Protocol::Tcp(port) => buf.extend_from_slice(&port.to_be_bytes()),
+            Protocol::Dns4(s) | Protocol::Dns6(s) | Protocol::Unix(s) | Protocol::Memory(s) => {
+                write_varint(buf, s.len() as u64);
+                buf.extend_from_slice(s.as_bytes());
+            }
+            Protocol::P2p(id) => {
+                write_varint(buf, id.len() as u64);
+                buf.extend_from_slice(id);
+            }
+            Protocol::Ws => (),
+        }
+    }
+
+    /// Read one protocol from the binary form, return it and the rest of data
+    fn from_bytes(data: &[u8]) -> Result<(Self, &[u8]), Error> {
+        fn split(data: &[u8], len: usize) -> Result<(&[u8], &[u8]), Error> {
+            if data.len() < len {
+                Err(Error::DataTooShort)
+            } else {
+                Ok(data.split_at(len))
+            }
+        }
+
+        fn string<'a>(name: &'static str, data: &'a [u8]) -> Result<(String, &'a [u8]), Error> {
+            let (len, rest) = read_varint(data)?;
+            let (value, rest) = split(rest, len as usize)?;
+            let value = String::from_utf8(value.to_vec()).map_err(|_| Error::InvalidValue(name))?;
+            Ok((value, rest))
+        }
+
+        let (code, data) = read_varint(data)?;
+        match code {
+            IP4 => {
+                let (value, rest) = split(data, 4)?;
+                let mut octets = [0; 4];
+                octets.copy_from_slice(value);
+                Ok((Protocol::Ip4(octets.into()), rest))
+            }
+            IP6 => {
+                let (value, rest) = split(data, 16)?;
+                let mut octets = [0; 16];
+                octets.copy_from_slice(value);
+                Ok((Protocol::Ip6(octets.into()), rest))
+            }
+            TCP => {
+                let (value, rest) = split(data, 2)?;
+                Ok((
+                    Protocol::Tcp(u16::from_be_bytes([value[0], value[1]])),
+                    rest,
+                ))
+            }
+            DNS4 => string("dns4", data).map(|(s, rest)| (Protocol::Dns4(s), rest)),
+            DNS6 => string("dns6", data).map(|(s, rest)| (Protocol::Dns6(s), rest)),
+            UNIX => string("unix", data).map(|(s, rest)| (Protocol::Unix(s), rest)),
+            MEMORY => string("memory", data).map(|(s, rest)| (Protocol::Memory(s), rest)),
+            WS => Ok((Protocol::Ws, data)),
+            P2P => {
+                let (len, rest) = read_varint(data)?;
+                let (value, rest) = split(rest, len as usize)?;
+                Ok((Protocol::P2p(value.to_vec()), rest))
+            }
+            code => Err(Error::UnknownProtocolCode(code)),
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Protocol::Ip4(ip) => write!(f, "/ip4/{}", ip),
+            Protocol::Ip6(ip) => write!(f, "/ip6/{}", ip),
+            Protocol::Dns4(name) => write!(f, "/dns4/{}", name),
+            Protocol::Dns6(name) => write!(f, "/dns6/{}", name),
+            Protocol::Tcp(port) => write!(f, "/tcp/{}", port),
+            Protocol::Unix(path) => write!(f, "/unix/{}", path.trim_start_matches('/')),
+            Protocol::Memory(name) => write!(f, "/memory/{}", name),
+            Protocol::Ws => write!(f, "/ws"),
+            Protocol::P2p(id) => write!(f, "/p2p/{}", bs58::encode(id).into_string()),
+        }
+    }
+}
+
+/// Composable, self-describing address, such as `/ip4/127.0.0.1/tcp/1337`,
+/// `/dns4/localhost/tcp/1337/ws`, `/unix/tmp/p2p.sock` or `/memory/node`.
+///
+/// It can be suffixed with `/p2p/<base58 peer id>` to specify the identity of the remote.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct Multiaddr {
+    protocols: Vec<Protocol>,
+}
+
+impl Multiaddr {
+    /// Create an empty multiaddr
+    pub fn empty() -> Self {
+        Default::default()
+    }
+
+    /// Decode from the binary form
+    pub fn from_bytes(mut data: &[u8]) -> Result<Self, Error> {
+        let mut protocols = Vec::new();
+        while !data.is_empty() {
+            let (protocol, rest) = Protocol::from_bytes(data)?;
+            protocols.push(protocol);
+            data = rest;
+        }
+        Ok(Multiaddr { protocols })
+    }
+
+    /// Encode to the binary form
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.protocols
+            .iter()
+            .for_each(|protocol| protocol.write_bytes(&mut buf));
+        buf
+    }
+
+    /// Append a protocol
+    pub fn push(&mut self, protocol: Protocol) {
+        self.protocols.push(protocol)
+    }
+
+    /// Remove and return the last protocol
+    pub fn pop(&mut self) -> Option<Protocol> {
+        self.protocols.pop()
+    }
+
+    /// Append a protocol and return self
+    pub fn with(mut self, protocol: Protocol) -> Self {
+        self.push(protocol);
+        self
+    }
+
+    /// Iterate over the protocols
+    pub fn iter(&self) -> slice::Iter<'_, Protocol> {
+        self.protocols.iter()
+    }
+
+    /// All the protocols
+    pub fn protocols(&self) -> &[Protocol] {
+        &self.protocols
+    }
+
+    /// Whether there are no protocols
+    pub fn is_empty(&self) -> bool {
+        self.protocols.is_empty()
+    }
+}
+
+impl FromStr for Multiaddr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('/');
+        if parts.next() != Some("") {
+            return Err(Error::InvalidMultiaddr);
+        }
+
+        let mut protocols = Vec::new();
+        while let Some(name) = parts.next() {
+            // Allow trailing slash
+            if name.is_empty() {
+                if parts.next().is_none() {
+                    break;
+                }
+                return Err(Error::UnknownProtocol(String::new()));
+            }
+            protocols.push(Protocol::from_str_parts(name, &mut parts)?);
+        }
+        Ok(Multiaddr { protocols })
+    }
+}
+
+impl fmt::Display for Multiaddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for protocol in self.protocols.iter() {
+            write!(f, "{}", protocol)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Multiaddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl From<Protocol> for Multiaddr {
+    fn from(protocol: Protocol) -> Self {
+        Multiaddr {
+            protocols: vec![protocol],
+        }
+    }
+}
+
+impl From<SocketAddr> for Multiaddr {
+    fn from(address: SocketAddr) -> Self {
+        let ip = match address.ip() {
+            IpAddr::V4(ip) => Protocol::Ip4(ip),
+            IpAddr::V6(ip) => Protocol::Ip6(ip),
+        };
+        Multiaddr {
+            protocols: vec![ip, Protocol::Tcp(address.port())],
+        }
+    }
+}
+
+impl FromIterator<Protocol> for Multiaddr {
+    fn from_iter<T: IntoIterator<Item = Protocol>>(iter: T) -> Self {
+        Multiaddr {
+            protocols: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Multiaddr {
+    type Item = &'a Protocol;
+    type IntoIter = slice::Iter<'a, Protocol>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.protocols.iter()
+    }
+}
+
+/// Convert `/ip4/<ip>/tcp/<port>` or `/ip6/<ip>/tcp/<port>` to socket address,
+/// a trailing `/p2p/<peer id>` is ignored
+pub fn multiaddr_to_socketaddr(address: &Multiaddr) -> Option<SocketAddr> {
+    match address.protocols() {
+        [Protocol::Ip4(ip), Protocol::Tcp(port)]
+        | [Protocol::Ip4(ip), Protocol::Tcp(port), Protocol::P2p(_)] => {
+            Some(SocketAddr::new(IpAddr::V4(*ip), *port))
+        }
+        [Protocol::Ip6(ip), Protocol::Tcp(port)]
+        | [Protocol::Ip6(ip), Protocol::Tcp(port), Protocol::P2p(_)] => {
+            Some(SocketAddr::new(IpAddr::V6(*ip), *port))
+        }
+        _ => None,
+    }
+}
+
+/// Get the ip of the address, if it starts with `/ip4` or `/ip6`
+pub fn multiaddr_to_ip(address: &Multiaddr) -> Option<IpAddr> {
+    match address.protocols().first() {
+        Some(Protocol::Ip4(ip)) => Some(IpAddr::V4(*ip)),
+        Some(Protocol::Ip6(ip)) => Some(IpAddr::V6(*ip)),
+        _ => None,
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &[u8]) -> Result<(u64, &[u8]), Error> {
+    let mut n: u64 = 0;
+    for (i, byte) in data.iter().enumerate().take(10) {
+        n |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((n, &data[i + 1..]));
+        }
+    }
+    Err(Error::DataTooShort)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{multiaddr_to_socketaddr, Error, Multiaddr, Protocol};
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_string_round_trip() {
+        for s in &[
+            "/ip4/127.0.0.1/tcp/1337",
+            "/ip6/::1/tcp/1337",
+            "/dns4/localhost/tcp/80/ws",
+            "/memory/node",
+            "/unix/tmp/p2p.sock",
+            "/ip4/1.2.3.4/tcp/1337/p2p/QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC",
+        ] {
+            let address: Multiaddr = s.parse().unwrap();
+            assert_eq!(&address.to_string(), s);
+        }
+        let address: Multiaddr = "/unix/tmp/p2p.sock".parse().unwrap();
+        assert_eq!(
+            address.protocols(),
+            &[Protocol::Unix("/tmp/p2p.sock".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let address: Multiaddr =
+            "/ip4/1.2.3.4/tcp/1337/ws/p2p/QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC"
+                .parse()
+                .unwrap();
+        let bytes = address.to_bytes();
+        assert_eq!(&bytes[..7], &[4, 1, 2, 3, 4, 6, 5]);
+        assert_eq!(Multiaddr::from_bytes(&bytes), Ok(address));
+        assert_eq!(
+            Multiaddr::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(Error::DataTooShort)
+        );
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert_eq!(
+            "ip4/127.0.0.1".parse::<Multiaddr>(),
+            Err(Error::InvalidMultiaddr)
+        );
+        assert_eq!(
+            "/ip4/127.0.0.1/udp/1".parse::<Multiaddr>(),
+            Err(Error::UnknownProtocol("udp".to_owned()))
+        );
+        assert_eq!(
+            "/ip4/127.0.0.1/tcp".parse::<Multiaddr>(),
+            Err(Error::MissingValue("tcp"))
+        );
+        assert_eq!(
+            "/ip4/127.0.0.1/tcp/65536".parse::<Multiaddr>(),
+            Err(Error::InvalidValue("tcp"))
+        );
+    }
+
+    #[test]
+    fn test_socketaddr_conversion() {
+        let socket: SocketAddr = "127.0.0.1:1337".parse().unwrap();
+        let address = Multiaddr::from(socket);
+        assert_eq!(address.to_string(), "/ip4/127.0.0.1/tcp/1337");
+        assert_eq!(multiaddr_to_socketaddr(&address), Some(socket));
+        assert_eq!(
+            multiaddr_to_socketaddr(&"/memory/node".parse().unwrap()),
+            None
+        );
+    }
+}
@@ -0,0 +1,398 @@
+use futures::{prelude::*, sync::oneshot};
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+use tokio::timer::Delay;
+
+use crate::{
+    channel::ServiceControl,
+    multiaddr::Multiaddr,
+    service::{Message, ProtocolHandle, ServiceContext},
+    session::{ProtocolId, SessionId},
+    PeerId, Priority, PublicKey, SessionType,
+};
+
+/// Frame kind of request
+const REQUEST: u8 = 0;
+/// Frame kind of response
+const RESPONSE: u8 = 1;
+/// Kind and request id
+const HEADER_LEN: usize = 9;
+
+/// Handle the inbound requests of a protocol
+///
+/// #### Note
+///
+/// It is called in the service like `ProtocolHandle`, do not insert long-time tasks,
+/// the responder can be moved to a futures task and respond later.
+pub trait RequestHandler {
+    /// Called when a request is received
+    fn handle_request(
+        &mut self,
+        control: &mut ServiceContext,
+        session_id: SessionId,
+        request: Vec<u8>,
+        responder: Responder,
+    );
+}
+
+/// Send the response of an inbound request
+pub struct Responder {
+    control: ServiceControl,
+    session_id: SessionId,
+    proto_id: ProtocolId,
+    request_id: u64,
+}
+
+impl Responder {
+    /// Session of the request
+    pub fn session_id(&self) -> SessionId {
+        self.session_id
+    }
+
+    /// Send the response
+    pub fn respond(mut self, response: Vec<u8>) -> Result<(), io::Error> {
+        self.control.send_message(
+            Some(vec![self.session_id]),
+            Message {
+                id: self.session_id,
+                proto_id: self.proto_id,
+                data: encode(RESPONSE, self.request_id, &response),
+                priority: Priority::Normal,
+            },
+        )
+    }
+}
+
+struct Pending {
+    session_id: SessionId,
+    sender: oneshot::Sender<Result<Vec<u8>, io::Error>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    control: Option<ServiceControl>,
+    next_request: u64,
+    /// Opened protocols of sessions
+    opened: HashSet<(SessionId, ProtocolId)>,
+    pending: HashMap<(ProtocolId, u64), Pending>,
+}
+
+/// Send requests over the protocols handled by its `RequestHandle`, cloneable.
+///
+/// The request and response are prefixed with a kind byte and an 8 bytes request id,
+/// so the protocols must not be shared with other handles.
+#[derive(Clone)]
+pub struct Requester {
+    inner: Arc<Mutex<Inner>>,
+    timeout: Duration,
+}
+
+impl Requester {
+    /// New a requester, the requests fail after the timeout
+    pub fn new(timeout: Duration) -> Self {
+        Requester {
+            inner: Arc::new(Mutex::new(Inner::default())),
+            timeout,
+        }
+    }
+
+    /// Create the protocol handle, return it in `ProtocolMeta::handle` of the protocol
+    pub fn handle<H>(&self, proto_id: ProtocolId, handler: H) -> RequestHandle<H>
+    where
+        H: RequestHandler,
+    {
+        RequestHandle {
+            proto_id,
+            handler,
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Send a request to the session, the future resolves to the response.
+    ///
+    /// Fail with `NotConnected` if the protocol is not open in the session,
+    /// `TimedOut` on timeout and `ConnectionAborted` if the protocol is closed before the response.
+    pub fn send_request(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        request: Vec<u8>,
+    ) -> Response {
+        let (sender, receiver) = oneshot::channel();
+        let mut inner = self.inner.lock().unwrap();
+        let request_id = inner.next_request;
+        inner.next_request = inner.next_request.wrapping_add(1);
+
+        let sent = if inner.opened.contains(&(session_id, proto_id)) {
+            let message = Message {
+                id: session_id,
+                proto_id,
+                data: encode(REQUEST, request_id, &request),
+                priority: Priority::Normal,
+            };
+            match inner.control {
+                Some(ref mut control) => control.send_message(Some(vec![session_id]), message),
+                None => Err(io::ErrorKind::NotConnected.into()),
+            }
+        } else {
+            Err(io::ErrorKind::NotConnected.into())
+        };
+        match sent {
+            Ok(()) => {
+                inner
+                    .pending
+                    .insert((proto_id, request_id), Pending { session_id, sender });
+            }
+            Err(err) => {
+                let _ = sender.send(Err(err));
+            }
+        }
+
+        Response {
+            key: (proto_id, request_id),
+            receiver,
+            delay: Delay::new(Instant::now() + self.timeout),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// The future of a response
+pub struct Response {
+    key: (ProtocolId, u64),
+    receiver: oneshot::Receiver<Result<Vec<u8>, io::Error>>,
+    delay: Delay,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Future for Response {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.receiver.poll() {
+            Ok(Async::Ready(Ok(response))) => return Ok(Async::Ready(response)),
+            Ok(Async::Ready(Err(err))) => return Err(err),
+            Ok(Async::NotReady) => (),
+            // The requester is dropped with the service
+            Err(_) => return Err(io::ErrorKind::BrokenPipe.into()),
+        }
+        match self.delay.poll() {
+            Ok(Async::Ready(())) => Err(io::ErrorKind::TimedOut.into()),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(err) => Err(io::Error::new(io::ErrorKind::Other, err)),
+        }
+    }
+}
+
+impl Drop for Response {
+    fn drop(&mut self) {
+        // Nobody waits for the response anymore
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.pending.remove(&self.key);
+        }
+    }
+}
+
+/// Protocol handle dispatching the requests and responses of a protocol
+pub struct RequestHandle<H> {
+    proto_id: ProtocolId,
+    handler: H,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl<H> ProtocolHandle for RequestHandle<H>
+where
+    H: RequestHandler,
+{
+    fn init(&mut self, control: &mut ServiceContext) {
+        self.inner.lock().unwrap().control = Some(control.control());
+    }
+
+    fn connected(
+        &mut self,
+        _control: &mut ServiceContext,
+        session_id: SessionId,
+        _address: Multiaddr,
+        _ty: SessionType,
+        _remote_public_key: &Option<PublicKey>,
+        _peer_id: Option<&PeerId>,
+        _version: &str,
+    ) {
+        self.inner
+            .lock()
+            .unwrap()
+            .opened
+            .insert((session_id, self.proto_id));
+    }
+
+    fn received(&mut self, control: &mut ServiceContext, mut message: Message) {
+        if message.data.len() < HEADER_LEN {
+            warn!(
+                "session [{}] proto [{}] invalid request frame",
+                message.id, message.proto_id
+            );
+            return;
+        }
+        let data = message.data.split_off(HEADER_LEN);
+        let mut id = [0; 8];
+        id.copy_from_slice(&message.data[1..HEADER_LEN]);
+        let request_id = u64::from_be_bytes(id);
+
+        match message.data[0] {
+            REQUEST => {
+                let responder = Responder {
+                    control: control.control(),
+                    session_id: message.id,
+                    proto_id: self.proto_id,
+                    request_id,
+                };
+                self.handler
+                    .handle_request(control, message.id, data, responder);
+            }
+            RESPONSE => {
+                let mut inner = self.inner.lock().unwrap();
+                let key = (self.proto_id, request_id);
+                // The response must come from the requested session
+                if inner
+                    .pending
+                    .get(&key)
+                    .is_some_and(|pending| pending.session_id == message.id)
+                {
+                    let pending = inner.pending.remove(&key).unwrap();
+                    let _ = pending.sender.send(Ok(data));
+                }
+            }
+            kind => warn!(
+                "session [{}] proto [{}] unknown request frame kind {}",
+                message.id, message.proto_id, kind
+            ),
+        }
+    }
+
+    fn disconnected(&mut self, _control: &mut ServiceContext, session_id: SessionId) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.opened.remove(&(session_id, self.proto_id));
+        let proto_id = self.proto_id;
+        let closed = inner
+            .pending
+            .iter()
+            .filter(|((id, _), pending)| *id == proto_id && pending.session_id == session_id)
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        for key in closed {
+            if let Some(pending) = inner.pending.remove(&key) {
+                let _ = pending
+                    .sender
+                    .send(Err(io::ErrorKind::ConnectionAborted.into()));
+            }
+        }
+    }
+}
+
+fn encode(kind: u8, request_id: u64, data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + data.len());
+    frame.push(kind);
+    frame.extend_from_slice(&request_id.to_be_bytes());
+    frame.extend_from_slice(data);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RequestHandler, Requester, Responder};
+    use crate::{
+        builder::ServiceBuilder,
+        channel::ChannelEvent,
+        codec::Codec,
+        service::{tests::connect_channels, ProtocolHandle, ServiceContext},
+        session::{ProtocolId, ProtocolMeta, SessionId},
+        transport::MemoryTransport,
+        SecioKeyPair,
+    };
+    use std::{io, time::Duration};
+    use tokio::{codec::length_delimited::LengthDelimitedCodec, runtime::Runtime};
+
+    /// Echo the request except "ignore"
+    struct Echo;
+
+    impl RequestHandler for Echo {
+        fn handle_request(
+            &mut self,
+            _control: &mut ServiceContext,
+            _session_id: SessionId,
+            request: Vec<u8>,
+            responder: Responder,
+        ) {
+            if request != b"ignore" {
+                responder.respond(request).unwrap();
+            }
+        }
+    }
+
+    struct RequestProtocol(Requester);
+
+    impl ProtocolMeta for RequestProtocol {
+        fn id(&self) -> ProtocolId {
+            1
+        }
+        fn codec(&self) -> Box<dyn Codec> {
+            Box::new(LengthDelimitedCodec::new())
+        }
+        fn handle(&self) -> Option<Box<dyn ProtocolHandle + Send + 'static>> {
+            Some(Box::new(self.0.handle(1, Echo)))
+        }
+    }
+
+    #[test]
+    fn test_request_response() {
+        let transport = MemoryTransport::new();
+        let create = |requester: &Requester| {
+            ServiceBuilder::default()
+                .insert_protocol(RequestProtocol(requester.clone()))
+                .key_pair(SecioKeyPair::secp256k1_generated())
+                .transport(transport.clone())
+        };
+        let requester = Requester::new(Duration::from_secs(1));
+        let (_server, mut client) = connect_channels(
+            create(&Requester::new(Duration::from_secs(1))),
+            create(&requester),
+        );
+
+        let id = client
+            .events
+            .by_ref()
+            .find_map(|event| match event {
+                Ok(ChannelEvent::ProtocolOpen { id, .. }) => Some(id),
+                _ => None,
+            })
+            .unwrap();
+
+        let mut runtime = Runtime::new().unwrap();
+        let response = runtime
+            .block_on(requester.send_request(id, 1, b"hello".to_vec()))
+            .unwrap();
+        assert_eq!(response, b"hello".to_vec());
+
+        let error = runtime
+            .block_on(requester.send_request(id, 1, b"ignore".to_vec()))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+
+        let error = runtime
+            .block_on(requester.send_request(id + 1, 1, b"hello".to_vec()))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotConnected);
+
+        // Pending requests fail when the protocol closes
+        let pending = requester.send_request(id, 1, b"ignore".to_vec());
+        client.control.close_protocol(id, 1).unwrap();
+        let error = runtime.block_on(pending).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionAborted);
+    }
+}
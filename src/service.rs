@@ -1,26 +1,46 @@
-use futures::{prelude::*, sync::mpsc};
+use futures::{future, prelude::*, sync::mpsc, task};
 use log::{debug, error, trace, warn};
-use secio::{handshake::Config, PublicKey, SecioKeyPair};
+use rand::Rng;
+use secio::{
+    codec::stats::TrafficStats, handshake::Config, Cipher, Digest, KeyAgreement, Negotiated,
+    PeerId, PublicKey, SecioKeyPair,
+};
 use std::collections::HashMap;
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
 use std::{
-    error::{self, Error},
+    cmp::min,
+    error::Error,
     io,
-    time::Duration,
-};
-use tokio::net::{
-    tcp::{ConnectFuture, Incoming},
-    TcpListener, TcpStream,
+    time::{Duration, Instant},
 };
 use tokio::{
-    codec::{Decoder, Encoder},
     prelude::{AsyncRead, AsyncWrite, FutureExt},
+    timer::Delay,
 };
-use yamux::session::SessionType;
+use yamux::{session::SessionType, Priority};
 
+use crate::ban::{BanList, BanTarget};
+use crate::channel::{ChannelEvent, ChannelSender, ServiceControl};
+use crate::metrics::Metrics;
+use crate::multiaddr::{multiaddr_to_ip, Multiaddr, Protocol};
 use crate::protocol_select::ProtocolInfo;
-use crate::session::{ProtocolId, ProtocolMeta, Session, SessionEvent, SessionId, SessionMeta};
+use crate::session::{
+    ProtocolConfigs, ProtocolId, ProtocolMeta, Session, SessionEvent, SessionId, SessionMeta,
+};
+use crate::transport::{BoxedConnection, Dialer, Incoming, Transport, TransportSet};
+
+/// The first redial delay of persistent peers
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// The max redial delay of persistent peers
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// The default timeout of connecting to the remote
+const DIAL_TIMEOUT: Duration = Duration::from_secs(10);
+/// The default timeout of shutdown, the sessions still open after it are closed forcibly
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+/// The default timeout of the secio handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// The default timeout of a protocol negotiation
+const PROTOCOL_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Service handle
 ///
@@ -64,6 +84,10 @@ pub trait ServiceHandle {
 /// The opening and closing of the protocol will create and clean up the handle exclusive
 /// to the session, but the global handle will remain in the state until the service is closed.
 ///
+/// If both sides open a protocol of a session at the same time, only the stream opened by
+/// the yamux client is kept. A side that opened the other stream first sees the protocol
+/// `connected`, `disconnected` and `connected` again.
+///
 pub trait ProtocolHandle {
     /// This function is called when the protocol is opened.
     ///
@@ -74,13 +98,15 @@ pub trait ProtocolHandle {
     /// Session exclusive handle can only receive messages from the own session
     fn received(&mut self, _control: &mut ServiceContext, _data: Message) {}
     /// Called when opening protocol
+    #[allow(clippy::too_many_arguments)]
     fn connected(
         &mut self,
         _control: &mut ServiceContext,
         _session_id: SessionId,
-        _address: SocketAddr,
+        _address: Multiaddr,
         _ty: SessionType,
         _remote_public_key: &Option<PublicKey>,
+        _peer_id: Option<&PeerId>,
         _version: &str,
     ) {
     }
@@ -105,6 +131,10 @@ pub struct Message {
     pub proto_id: ProtocolId,
     /// Data
     pub data: Vec<u8>,
+    /// Priority of sending, higher priority messages are sent first.
+    ///
+    /// The received messages are always `Priority::Normal`
+    pub priority: Priority,
 }
 
 impl Default for Message {
@@ -113,6 +143,7 @@ impl Default for Message {
             id: 0,
             proto_id: 0,
             data: Vec::new(),
+            priority: Priority::Normal,
         }
     }
 }
@@ -124,7 +155,13 @@ impl Default for Message {
 pub struct ServiceContext {
     service_task_sender: mpsc::Sender<ServiceTask>,
     proto_infos: Arc<HashMap<ProtocolId, ProtocolInfo>>,
-    listens: Vec<SocketAddr>,
+    listens: Vec<Multiaddr>,
+    bans: BanList,
+    sessions: HashMap<SessionId, SessionInfo>,
+    /// Messages dropped because the service task channel is full, reported by the service
+    dropped_messages: Vec<(SessionId, ProtocolId)>,
+    /// Messages to a peer dropped because the service task channel is full
+    dropped_peer_messages: Vec<(PeerId, ProtocolId)>,
 }
 
 impl ServiceContext {
@@ -137,13 +174,35 @@ impl ServiceContext {
             service_task_sender,
             proto_infos: Arc::new(proto_infos),
             listens: Vec::new(),
+            bans: BanList::default(),
+            sessions: HashMap::new(),
+            dropped_messages: Vec::new(),
+            dropped_peer_messages: Vec::new(),
         }
     }
 
     /// Initiate a connection request to address
     #[inline]
-    pub fn dial(&mut self, address: SocketAddr) {
-        self.send(ServiceTask::Dial { address })
+    pub fn dial(&mut self, address: Multiaddr) {
+        self.dial_with(address, DialOptions::default())
+    }
+
+    /// Initiate a connection request to address with options
+    #[inline]
+    pub fn dial_with(&mut self, address: Multiaddr, options: DialOptions) {
+        self.send(ServiceTask::Dial { address, options })
+    }
+
+    /// Keep a connection to the address, redial it after dial failure or session close
+    #[inline]
+    pub fn add_persistent_peer(&mut self, address: Multiaddr) {
+        self.send(ServiceTask::AddPersistentPeer { address })
+    }
+
+    /// Stop redialing the address, the current session is kept
+    #[inline]
+    pub fn remove_persistent_peer(&mut self, address: Multiaddr) {
+        self.send(ServiceTask::RemovePersistentPeer { address })
     }
 
     /// Disconnect a connection
@@ -152,12 +211,55 @@ impl ServiceContext {
         self.send(ServiceTask::Disconnect { id })
     }
 
-    /// Send message
+    /// Disconnect the session with the peer
+    #[inline]
+    pub fn disconnect_peer(&mut self, peer_id: PeerId) {
+        self.send(ServiceTask::DisconnectPeer { peer_id })
+    }
+
+    /// Open a protocol of the session if it's not open
+    #[inline]
+    pub fn open_protocol(&mut self, id: SessionId, proto_id: ProtocolId) {
+        self.send(ServiceTask::ProtocolOpen { id, proto_id })
+    }
+
+    /// Close a protocol of the session, the session is kept
+    #[inline]
+    pub fn close_protocol(&mut self, id: SessionId, proto_id: ProtocolId) {
+        self.send(ServiceTask::ProtocolClose { id, proto_id })
+    }
+
+    /// Ban an address or a public key for a duration, the sessions with it will be closed
+    #[inline]
+    pub fn ban<B: Into<BanTarget>>(&mut self, target: B, duration: Duration) {
+        self.send(ServiceTask::Ban {
+            target: target.into(),
+            duration,
+        })
+    }
+
+    /// Lift the ban of an address or a public key
+    #[inline]
+    pub fn unban<B: Into<BanTarget>>(&mut self, target: B) {
+        self.send(ServiceTask::Unban {
+            target: target.into(),
+        })
+    }
+
+    /// Send message, `ServiceEvent::MessageDropped` is reported if it can't be queued
     #[inline]
     pub fn send_message(&mut self, ids: Option<Vec<SessionId>>, message: Message) {
         self.send(ServiceTask::ProtocolMessage { ids, message })
     }
 
+    /// Send message to the session with the peer, the session id of the message is ignored.
+    ///
+    /// `ServiceEvent::MessageDropped` with the peer id is reported if the peer is not connected
+    #[inline]
+    pub fn send_message_to_peer(&mut self, peer_id: PeerId, message: Message) {
+        self.send(ServiceTask::PeerMessage { peer_id, message })
+    }
+
     /// Send a future task
     #[inline]
     pub fn future_task<T>(&mut self, task: T)
@@ -175,6 +277,12 @@ impl ServiceContext {
         &mut self.service_task_sender
     }
 
+    /// Get a cloneable handle to send commands to the service, it can be moved to other tasks
+    #[inline]
+    pub fn control(&self) -> ServiceControl {
+        ServiceControl::new(self.service_task_sender.clone())
+    }
+
     /// Get service protocol message, Map(ID, Name), but can't modify
     #[inline]
     pub fn protocols(&self) -> &Arc<HashMap<ProtocolId, ProtocolInfo>> {
@@ -183,21 +291,67 @@ impl ServiceContext {
 
     /// Get service listen address list
     #[inline]
-    pub fn listens(&self) -> &Vec<SocketAddr> {
+    pub fn listens(&self) -> &Vec<Multiaddr> {
         &self.listens
     }
 
+    /// Get service ban list
+    #[inline]
+    pub fn bans(&self) -> &BanList {
+        &self.bans
+    }
+
+    /// Get the open sessions
+    #[inline]
+    pub fn sessions(&self) -> &HashMap<SessionId, SessionInfo> {
+        &self.sessions
+    }
+
+    /// Get the session by id
+    #[inline]
+    pub fn session(&self, id: SessionId) -> Option<&SessionInfo> {
+        self.sessions.get(&id)
+    }
+
+    /// Shutdown service gracefully, the service stream ends after all sessions are closed
+    #[inline]
+    pub fn shutdown(&mut self) {
+        self.send(ServiceTask::Shutdown)
+    }
+
     /// Real send function
     #[inline]
     fn send(&mut self, event: ServiceTask) {
-        let _ = self.service_task_sender.try_send(event);
+        if let Err(err) = self.service_task_sender.try_send(event) {
+            if !err.is_full() {
+                // The service is stopped
+                return;
+            }
+            match err.into_inner() {
+                ServiceTask::ProtocolMessage { ids, message } => {
+                    let ids = ids.unwrap_or_else(|| self.sessions.keys().cloned().collect());
+                    self.dropped_messages
+                        .extend(ids.into_iter().map(|id| (id, message.proto_id)));
+                }
+                ServiceTask::PeerMessage { peer_id, message } => {
+                    self.dropped_peer_messages.push((peer_id, message.proto_id));
+                }
+                _ => (),
+            }
+        }
     }
 
     /// Update listen list
     #[inline]
-    fn update_listens(&mut self, address_list: Vec<SocketAddr>) {
+    fn update_listens(&mut self, address_list: Vec<Multiaddr>) {
         self.listens = address_list;
     }
+
+    /// Update ban list
+    #[inline]
+    fn update_bans(&mut self, bans: BanList) {
+        self.bans = bans;
+    }
 }
 
 /// Event generated by the Service
@@ -206,14 +360,27 @@ pub enum ServiceEvent {
     /// When dial remote error
     DialerError {
         /// Remote address
-        address: SocketAddr,
+        address: Multiaddr,
         /// Io error
         error: io::Error,
+        /// Tag of the dial options
+        tag: Option<u64>,
+    },
+    /// The dialed remote is not the expected peer, the connection is closed after handshake
+    UnexpectedPeer {
+        /// Remote address
+        address: Multiaddr,
+        /// The expected peer in the dial options
+        expected: PeerId,
+        /// The peer of the handshake
+        actual: PeerId,
+        /// Tag of the dial options
+        tag: Option<u64>,
     },
     /// When listen error
     ListenError {
         /// Listen address
-        address: SocketAddr,
+        address: Multiaddr,
         /// Io error
         error: io::Error,
     },
@@ -221,18 +388,248 @@ pub enum ServiceEvent {
     SessionClose {
         /// Session id
         id: SessionId,
+        /// Why the session is closed
+        reason: CloseReason,
     },
     /// A session open
     SessionOpen {
         /// Session id
         id: SessionId,
         /// Remote address
-        address: SocketAddr,
+        address: Multiaddr,
         /// Outbound or Inbound
         ty: SessionType,
         /// Remote public key
         public_key: Option<PublicKey>,
+        /// Remote peer id, None without secio
+        peer_id: Option<PeerId>,
+        /// The algorithms chosen by the secio handshake, None without secio
+        negotiated: Option<Negotiated>,
+        /// Tag of the dial options, None if inbound
+        tag: Option<u64>,
+    },
+    /// The secio handshake of an inbound or outbound connection failed
+    HandshakeError {
+        /// Remote address
+        address: Multiaddr,
+        /// Outbound or Inbound
+        ty: SessionType,
+        /// Io error
+        error: io::Error,
+        /// Tag of the dial options, None if inbound
+        tag: Option<u64>,
+    },
+    /// Two connections with the same peer, only one of them is kept.
+    ///
+    /// Both sides keep the connection dialed by the peer with the lower peer id.
+    /// If both are dialed by the same side, they keep the one whose secio handshake
+    /// nonces, sorted, are higher. Without secio the existing connection is always kept
+    DuplicateConnection {
+        /// Remote peer id
+        peer_id: PeerId,
+        /// The session kept
+        kept: SessionId,
+        /// Outbound or Inbound of the kept session
+        kept_ty: SessionType,
+        /// Remote address of the closed connection
+        closed_address: Multiaddr,
+        /// Outbound or Inbound of the closed connection
+        closed_ty: SessionType,
+        /// Tag of the dial options if the closed connection is the new outbound one,
+        /// no other event comes for that dial
+        closed_tag: Option<u64>,
+    },
+    /// A connection is closed before handshake because of the connection limits
+    ConnectionRejected {
+        /// Remote address
+        address: Multiaddr,
+        /// Outbound or Inbound
+        ty: SessionType,
+        /// The exceeded limit
+        limit: ConnectionLimit,
+        /// Tag of the dial options, None if inbound
+        tag: Option<u64>,
     },
+    /// Redial a persistent peer
+    Reconnect {
+        /// Remote address
+        address: Multiaddr,
+        /// Number of the attempts since the last session open, start from 1
+        attempt: u32,
+    },
+    /// A message to send is dropped
+    MessageDropped {
+        /// Session id, None if the message is sent to a peer that is not connected
+        session_id: Option<SessionId>,
+        /// Remote peer id, None if the session is not found or has no peer id
+        peer_id: Option<PeerId>,
+        /// Protocol id
+        proto_id: ProtocolId,
+        /// Where the message is dropped
+        reason: DropReason,
+    },
+}
+
+/// The reason of a dropped message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropReason {
+    /// The task channel of the service is full
+    ServiceBusy,
+    /// The channel of the session is full
+    SessionBusy,
+    /// The session is not found or closed
+    SessionClosed,
+    /// The channel of the protocol stream is full
+    ProtocolBusy,
+    /// The protocol is not open or is closing in the session
+    ProtocolNotOpen,
+    /// No session with the peer
+    PeerNotConnected,
+}
+
+/// The reason of a closed session
+#[derive(Debug)]
+pub enum CloseReason {
+    /// The remote closed the connection
+    RemoteEof,
+    /// The remote sent a GoAway before closing the connection
+    GoAway,
+    /// The remote didn't answer the keepalive ping in time
+    KeepaliveTimeout,
+    /// Closed by the local service, such as disconnect, ban or shutdown
+    LocalDisconnect,
+    /// The connection failed
+    Error(io::Error),
+}
+
+/// The state of an open session
+#[derive(Clone, Debug)]
+pub struct SessionInfo {
+    /// Session id
+    pub id: SessionId,
+    /// Remote address
+    pub address: Multiaddr,
+    /// Remote public key
+    pub public_key: Option<PublicKey>,
+    /// Remote peer id, None without secio
+    pub peer_id: Option<PeerId>,
+    /// Outbound or Inbound
+    pub ty: SessionType,
+    /// The algorithms chosen by the secio handshake, None without secio
+    pub negotiated: Option<Negotiated>,
+    /// Open protocols and their negotiated versions
+    pub protocols: HashMap<ProtocolId, String>,
+    /// The time of session open
+    pub connected_at: Instant,
+    /// Protocol messages of the session
+    pub traffic: Arc<TrafficStats>,
+    /// Encrypted frames of the connection, None without secio
+    pub secure_traffic: Option<Arc<TrafficStats>>,
+    /// Frames of the open protocols
+    pub protocol_traffic: HashMap<ProtocolId, Arc<TrafficStats>>,
+}
+
+/// Limits on the number of sessions, the sessions in handshake are also counted.
+///
+/// None means unlimited
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionLimits {
+    /// Max number of all sessions
+    pub max_sessions: Option<usize>,
+    /// Max number of inbound sessions
+    pub max_inbound: Option<usize>,
+    /// Max number of outbound sessions
+    pub max_outbound: Option<usize>,
+    /// Max number of sessions with the same remote ip
+    pub max_per_ip: Option<usize>,
+}
+
+/// Configuration of the connections and the sessions on them
+#[derive(Clone, Debug)]
+pub struct SessionConfig {
+    /// Yamux config of the sessions, such as the window size
+    pub yamux: yamux::Config,
+    /// Key agreements proposed in the secio handshake, None means all supported
+    pub key_agreements: Option<Vec<KeyAgreement>>,
+    /// Ciphers proposed in the secio handshake, None means all supported
+    pub ciphers: Option<Vec<Cipher>>,
+    /// Digests proposed in the secio handshake, None means all supported
+    pub digests: Option<Vec<Digest>>,
+    /// Timeout of the secio handshake
+    pub handshake_timeout: Duration,
+    /// Timeout of a protocol negotiation
+    pub protocol_timeout: Duration,
+    /// Timeout of flushing the sessions on shutdown, the sessions still open are closed forcibly
+    pub shutdown_timeout: Duration,
+}
+
+impl SessionConfig {
+    /// Secio handshake config with the proposals
+    fn secio(&self, key_pair: SecioKeyPair) -> Config {
+        let mut config = Config::new(key_pair);
+        if let Some(ref key_agreements) = self.key_agreements {
+            config = config.key_agreements(key_agreements);
+        }
+        if let Some(ref ciphers) = self.ciphers {
+            config = config.ciphers(ciphers);
+        }
+        if let Some(ref digests) = self.digests {
+            config = config.digests(digests);
+        }
+        config
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            yamux: yamux::Config::default(),
+            key_agreements: None,
+            ciphers: None,
+            digests: None,
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+            protocol_timeout: PROTOCOL_TIMEOUT,
+            shutdown_timeout: SHUTDOWN_TIMEOUT,
+        }
+    }
+}
+
+/// Options of a dial
+#[derive(Clone, Debug)]
+pub struct DialOptions {
+    /// Timeout of connecting to the remote, the secio handshake is not included
+    pub timeout: Duration,
+    /// Abort the connection with `UnexpectedPeer` if the remote is not this peer,
+    /// a public key converts into its peer id.
+    ///
+    /// If None, the peer id of a trailing `/p2p/<peer id>` of the address is used
+    pub peer_id: Option<PeerId>,
+    /// Returned in the `SessionOpen`, `DialerError`, `HandshakeError`, `UnexpectedPeer`,
+    /// `ConnectionRejected` or `DuplicateConnection` event of this dial
+    pub tag: Option<u64>,
+}
+
+impl Default for DialOptions {
+    fn default() -> Self {
+        DialOptions {
+            timeout: DIAL_TIMEOUT,
+            peer_id: None,
+            tag: None,
+        }
+    }
+}
+
+/// The limit exceeded by a rejected connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionLimit {
+    /// Max number of all sessions
+    Sessions,
+    /// Max number of inbound sessions
+    Inbound,
+    /// Max number of outbound sessions
+    Outbound,
+    /// Max number of sessions with the same remote ip
+    PerIp,
 }
 
 /// Task received by the Service.
@@ -268,36 +665,215 @@ pub enum ServiceTask {
         /// Future
         task: Box<dyn Future<Item = (), Error = ()> + 'static + Send>,
     },
+    /// Send protocol data to a peer task
+    PeerMessage {
+        /// Peer id
+        peer_id: PeerId,
+        /// data
+        message: Message,
+    },
     /// Disconnect task
     Disconnect {
         /// Session id
         id: SessionId,
     },
+    /// Disconnect a peer task
+    DisconnectPeer {
+        /// Peer id
+        peer_id: PeerId,
+    },
+    /// Open protocol task
+    ProtocolOpen {
+        /// Session id
+        id: SessionId,
+        /// Protocol id
+        proto_id: ProtocolId,
+    },
+    /// Close protocol task
+    ProtocolClose {
+        /// Session id
+        id: SessionId,
+        /// Protocol id
+        proto_id: ProtocolId,
+    },
     /// Dial task
     Dial {
         /// Remote address
-        address: SocketAddr,
+        address: Multiaddr,
+        /// Dial options
+        options: DialOptions,
+    },
+    /// Ban task
+    Ban {
+        /// Address or public key
+        target: BanTarget,
+        /// Ban duration
+        duration: Duration,
+    },
+    /// Unban task
+    Unban {
+        /// Address or public key
+        target: BanTarget,
+    },
+    /// Add persistent peer task
+    AddPersistentPeer {
+        /// Remote address
+        address: Multiaddr,
+    },
+    /// Remove persistent peer task
+    RemovePersistentPeer {
+        /// Remote address
+        address: Multiaddr,
+    },
+    /// A protocol is registered at runtime
+    ProtocolRegistered {
+        /// Protocol id
+        proto_id: ProtocolId,
+    },
+    /// A protocol is unregistered at runtime
+    ProtocolUnregistered {
+        /// Protocol id
+        proto_id: ProtocolId,
     },
+    /// Shutdown task
+    Shutdown,
+}
+
+/// Handle to shutdown the service from outside
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    service_task_sender: mpsc::Sender<ServiceTask>,
+}
+
+impl ShutdownHandle {
+    /// Shutdown service gracefully, the service stream ends after all sessions are closed
+    pub fn shutdown(&mut self) {
+        let _ = self.service_task_sender.try_send(ServiceTask::Shutdown);
+    }
+}
+
+/// Handle to register and unregister the protocols of a running service, cloneable
+pub struct ProtocolRegistry {
+    protocol_configs: ProtocolConfigs,
+    service_task_sender: mpsc::Sender<ServiceTask>,
+}
+
+impl ProtocolRegistry {
+    /// Register a protocol, the new sessions and protocol negotiations can open it.
+    ///
+    /// Fail with `AlreadyExists` if the id or the name is registered
+    pub fn register<P>(&mut self, protocol: P) -> Result<(), io::Error>
+    where
+        P: ProtocolMeta + Send + Sync + 'static,
+    {
+        let (proto_id, name) = (protocol.id(), protocol.name());
+        {
+            let mut configs = self.protocol_configs.write().unwrap();
+            if configs.contains_key(&name) || configs.values().any(|proto| proto.id() == proto_id) {
+                return Err(io::ErrorKind::AlreadyExists.into());
+            }
+            configs.insert(name.clone(), Box::new(protocol));
+        }
+        let result = self.notify(ServiceTask::ProtocolRegistered { proto_id });
+        if result.is_err() {
+            self.protocol_configs.write().unwrap().remove(&name);
+        }
+        result
+    }
+
+    /// Unregister a protocol, it's closed on the open sessions.
+    ///
+    /// Fail with `NotFound` if the protocol is not registered
+    pub fn unregister(&mut self, proto_id: ProtocolId) -> Result<(), io::Error> {
+        let removed = {
+            let mut configs = self.protocol_configs.write().unwrap();
+            let name = configs
+                .iter()
+                .find(|(_, proto)| proto.id() == proto_id)
+                .map(|(name, _)| name.clone())
+                .ok_or(io::ErrorKind::NotFound)?;
+            configs.remove_entry(&name).unwrap()
+        };
+        let result = self.notify(ServiceTask::ProtocolUnregistered { proto_id });
+        if result.is_err() {
+            let (name, protocol) = removed;
+            self.protocol_configs
+                .write()
+                .unwrap()
+                .insert(name, protocol);
+        }
+        result
+    }
+
+    fn notify(&mut self, task: ServiceTask) -> Result<(), io::Error> {
+        self.service_task_sender.try_send(task).map_err(|err| {
+            if err.is_full() {
+                io::ErrorKind::WouldBlock.into()
+            } else {
+                io::ErrorKind::BrokenPipe.into()
+            }
+        })
+    }
+}
+
+impl Clone for ProtocolRegistry {
+    fn clone(&self) -> Self {
+        ProtocolRegistry {
+            protocol_configs: self.protocol_configs.clone(),
+            service_task_sender: self.service_task_sender.clone(),
+        }
+    }
+}
+
+/// The session state held by service
+struct SessionController {
+    sender: mpsc::Sender<SessionEvent>,
+    address: Multiaddr,
+    ty: SessionType,
+    /// The nonces of the secio handshake
+    nonces: Option<Vec<u8>>,
 }
 
-/// An abstraction of p2p service, currently only supports TCP protocol
-pub struct Service<T, U> {
-    protocol_configs: Arc<HashMap<String, Box<dyn ProtocolMeta<U> + Send + Sync>>>,
+/// An abstraction of p2p service, the underlying connections are provided by the transports
+pub struct Service<T> {
+    protocol_configs: ProtocolConfigs,
+
+    sessions: HashMap<SessionId, SessionController>,
+
+    /// Connections in secio handshake
+    handshakes: Vec<(Multiaddr, SessionType)>,
+
+    limits: ConnectionLimits,
 
-    sessions: HashMap<SessionId, mpsc::Sender<SessionEvent>>,
+    config: SessionConfig,
 
-    listens: Vec<(SocketAddr, Incoming)>,
+    bans: BanList,
 
-    dial: Vec<(SocketAddr, ConnectFuture)>,
+    listens: Vec<(Multiaddr, Incoming)>,
+
+    dial: Vec<(Multiaddr, DialOptions, Dialer)>,
+
+    /// Persistent peers and their failed attempts since the last session open
+    persistent_peers: HashMap<Multiaddr, u32>,
+    /// Persistent peers waiting for redial
+    redials: Vec<(Multiaddr, Delay)>,
+
+    transport: TransportSet,
     /// Calculate the number of connection requests that need to be sent externally,
     /// if run forever, it will default to 1, else it default to 0
     task_count: usize,
 
+    forever: bool,
+    /// The deadline of shutdown, Some if shutting down
+    shutdown: Option<Delay>,
+
     next_session: SessionId,
 
     key_pair: Option<SecioKeyPair>,
 
     remote_pubkeys: HashMap<SessionId, PublicKey>,
+    /// The session of each connected peer
+    peer_sessions: HashMap<PeerId, SessionId>,
 
     /// Can be upgrade to list service level protocols
     handle: T,
@@ -316,113 +892,186 @@ pub struct Service<T, U> {
     service_context: ServiceContext,
     /// External event receiver
     service_task_receiver: mpsc::Receiver<ServiceTask>,
+    /// Protocol events are also sent here in channel mode
+    event_sender: Option<Arc<Mutex<ChannelSender>>>,
+
+    metrics: Arc<Metrics>,
 }
 
-impl<T, U> Service<T, U>
+impl<T> Service<T>
 where
     T: ServiceHandle,
-    U: Decoder<Item = bytes::BytesMut> + Encoder<Item = bytes::Bytes> + Send + 'static,
-    <U as Decoder>::Error: error::Error + Into<io::Error>,
-    <U as Encoder>::Error: error::Error + Into<io::Error>,
 {
     /// New a Service
     pub fn new(
-        protocol_configs: Arc<HashMap<String, Box<dyn ProtocolMeta<U> + Send + Sync>>>,
+        protocol_configs: HashMap<String, Box<dyn ProtocolMeta + Send + Sync>>,
         handle: T,
         key_pair: Option<SecioKeyPair>,
         forever: bool,
+        transports: Vec<Box<dyn Transport + Send>>,
+        limits: ConnectionLimits,
+        config: SessionConfig,
     ) -> Self {
         let (session_event_sender, session_event_receiver) = mpsc::channel(256);
         let (service_task_sender, service_task_receiver) = mpsc::channel(256);
-        let proto_infos = protocol_configs
-            .values()
-            .map(|meta| {
-                let proto_info = ProtocolInfo::new(&meta.name(), meta.support_versions());
-                (meta.id(), proto_info)
-            })
-            .collect();
+        let proto_infos = proto_infos(&protocol_configs);
 
         Service {
-            protocol_configs,
+            protocol_configs: Arc::new(RwLock::new(protocol_configs)),
             handle,
             key_pair,
             sessions: HashMap::default(),
+            handshakes: Vec::new(),
+            limits,
+            config,
+            bans: BanList::default(),
             remote_pubkeys: HashMap::new(),
+            peer_sessions: HashMap::new(),
             proto_handles: HashMap::default(),
             proto_session_handles: HashMap::default(),
             listens: Vec::new(),
             dial: Vec::new(),
+            persistent_peers: HashMap::new(),
+            redials: Vec::new(),
+            transport: TransportSet::new(transports),
             task_count: if forever { 1 } else { 0 },
+            forever,
+            shutdown: None,
             next_session: 0,
             session_event_sender,
             session_event_receiver,
             service_context: ServiceContext::new(service_task_sender, proto_infos),
             service_task_receiver,
+            event_sender: None,
+            metrics: Arc::new(Metrics::default()),
         }
     }
 
-    /// Listen on the given address.
-    pub fn listen(&mut self, address: SocketAddr) -> Result<(), io::Error> {
-        let tcp = TcpListener::bind(&address)?;
-        self.listens.push((address, tcp.incoming()));
-        Ok(())
+    /// Send the protocol events to the channel, the metrics are shared with the channel handle
+    pub(crate) fn event_sender(
+        &mut self,
+        sender: Arc<Mutex<ChannelSender>>,
+        metrics: Arc<Metrics>,
+    ) {
+        self.event_sender = Some(sender);
+        self.metrics = metrics;
+    }
+
+    /// Listen on the given address, return the actual listen address.
+    pub fn listen(&mut self, address: Multiaddr) -> Result<Multiaddr, io::Error> {
+        let (listen_address, incoming) = self.transport.listen(address)?;
+        self.listens.push((listen_address.clone(), incoming));
+        Ok(listen_address)
     }
 
     /// Dial the given address, doesn't actually make a request, just generate a future
-    pub fn dial(mut self, address: SocketAddr) -> Self {
-        let dial = TcpStream::connect(&address);
-        self.dial.push((address, dial));
-        self.task_count += 1;
+    pub fn dial(self, address: Multiaddr) -> Self {
+        self.dial_with(address, DialOptions::default())
+    }
+
+    /// Dial the given address with options, doesn't actually make a request, just generate a future
+    pub fn dial_with(mut self, address: Multiaddr, options: DialOptions) -> Self {
+        self.dial_inner(address, options);
         self
     }
 
+    /// Get service ban list
+    pub fn bans(&self) -> &BanList {
+        &self.bans
+    }
+
+    /// Get a handle to shutdown the service
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            service_task_sender: self.service_context.service_task_sender.clone(),
+        }
+    }
+
+    /// Get a cloneable handle to send commands to the service
+    pub fn control(&self) -> ServiceControl {
+        ServiceControl::new(self.service_context.service_task_sender.clone())
+    }
+
+    /// Get the metrics of the service, render or serve them by the `metrics` module
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
     /// Get service current protocol configure
     pub fn get_protocol_configs(
         &self,
-    ) -> &Arc<HashMap<String, Box<dyn ProtocolMeta<U> + Send + Sync>>> {
-        &self.protocol_configs
+    ) -> RwLockReadGuard<'_, HashMap<String, Box<dyn ProtocolMeta + Send + Sync>>> {
+        self.protocol_configs.read().unwrap()
+    }
+
+    /// Get a cloneable handle to register and unregister protocols at runtime
+    pub fn registry(&self) -> ProtocolRegistry {
+        ProtocolRegistry {
+            protocol_configs: self.protocol_configs.clone(),
+            service_task_sender: self.service_context.service_task_sender.clone(),
+        }
+    }
+
+    /// Refresh the protocol infos of the context after the registry is changed
+    fn update_proto_infos(&mut self) {
+        let proto_infos = proto_infos(&self.protocol_configs.read().unwrap());
+        self.service_context.proto_infos = Arc::new(proto_infos);
     }
 
     /// Send data to the specified protocol for the specified session.
     ///
-    /// Valid after Service starts
+    /// Valid after Service starts, `ServiceEvent::MessageDropped` is reported if it can't be queued
     #[inline]
     pub fn send_message(&mut self, message: Message) {
-        if let Some(sender) = self.sessions.get_mut(&message.id) {
-            let _ = sender.try_send(SessionEvent::ProtocolMessage {
-                id: message.id,
-                proto_id: message.proto_id,
-                data: message.data.into(),
-            });
-        }
+        self.filter_broadcast(Some(vec![message.id]), message)
     }
 
     /// Send data to the specified protocol for the specified sessions.
     ///
-    /// Valid after Service starts
+    /// Valid after Service starts, `ServiceEvent::MessageDropped` is reported for each session
+    /// the message can't be queued to
     #[inline]
     pub fn filter_broadcast(&mut self, ids: Option<Vec<SessionId>>, message: Message) {
-        match ids {
-            None => self.broadcast(message),
-            Some(ids) => {
-                let proto_id = message.proto_id;
-                let data: bytes::Bytes = message.data.into();
-                self.sessions.iter_mut().for_each(|(id, send)| {
-                    if ids.contains(id) {
-                        let _ = send.try_send(SessionEvent::ProtocolMessage {
-                            id: *id,
-                            proto_id,
-                            data: data.clone(),
-                        });
-                    }
-                });
+        let ids = match ids {
+            Some(ids) => ids,
+            None => return self.broadcast(message),
+        };
+        let proto_id = message.proto_id;
+        let priority = message.priority;
+        let data: bytes::Bytes = message.data.into();
+        for id in ids {
+            let result = match self.sessions.get_mut(&id) {
+                Some(session) => session
+                    .sender
+                    .try_send(SessionEvent::ProtocolMessage {
+                        id,
+                        proto_id,
+                        data: data.clone(),
+                        priority,
+                    })
+                    .map_err(|err| {
+                        if err.is_full() {
+                            DropReason::SessionBusy
+                        } else {
+                            DropReason::SessionClosed
+                        }
+                    }),
+                None => Err(DropReason::SessionClosed),
+            };
+            if let Err(reason) = result {
+                if reason == DropReason::SessionBusy {
+                    self.metrics.channel_overflow();
+                }
+                self.metrics.traffic().record_dropped();
+                self.message_dropped(id, proto_id, reason);
             }
         }
     }
 
     /// Broadcast data for a specified protocol.
     ///
-    /// Valid after Service starts
+    /// Valid after Service starts, `ServiceEvent::MessageDropped` is reported for each session
+    /// the message can't be queued to
     #[inline]
     pub fn broadcast(&mut self, message: Message) {
         debug!(
@@ -430,15 +1079,70 @@ where
             self.sessions.len(),
             message.proto_id
         );
-        let proto_id = message.proto_id;
-        let data: bytes::Bytes = message.data.into();
-        self.sessions.iter_mut().for_each(|(id, send)| {
-            let _ = send.try_send(SessionEvent::ProtocolMessage {
-                id: *id,
+        let ids = self.sessions.keys().cloned().collect();
+        self.filter_broadcast(Some(ids), message)
+    }
+
+    /// Report a dropped message to the service handle
+    fn message_dropped(&mut self, session_id: SessionId, proto_id: ProtocolId, reason: DropReason) {
+        debug!(
+            "session [{}] proto [{}] message dropped: {:?}",
+            session_id, proto_id, reason
+        );
+        let peer_id = self
+            .service_context
+            .sessions
+            .get(&session_id)
+            .and_then(|info| info.peer_id.clone());
+        self.handle.handle_error(
+            &mut self.service_context,
+            ServiceEvent::MessageDropped {
+                session_id: Some(session_id),
+                peer_id,
                 proto_id,
-                data: data.clone(),
-            });
-        });
+                reason,
+            },
+        );
+    }
+
+    /// Report a dropped message to the peer to the service handle
+    fn peer_message_dropped(&mut self, peer_id: PeerId, proto_id: ProtocolId, reason: DropReason) {
+        debug!(
+            "peer {} proto [{}] message dropped: {:?}",
+            peer_id, proto_id, reason
+        );
+        let session_id = self.peer_session(&peer_id);
+        self.handle.handle_error(
+            &mut self.service_context,
+            ServiceEvent::MessageDropped {
+                session_id,
+                peer_id: Some(peer_id),
+                proto_id,
+                reason,
+            },
+        );
+    }
+
+    /// Report the messages dropped by the service context
+    fn context_dropped(&mut self) {
+        let dropped = ::std::mem::take(&mut self.service_context.dropped_messages);
+        for (session_id, proto_id) in dropped {
+            self.metrics.channel_overflow();
+            self.metrics.traffic().record_dropped();
+            self.message_dropped(session_id, proto_id, DropReason::ServiceBusy);
+        }
+        let dropped = ::std::mem::take(&mut self.service_context.dropped_peer_messages);
+        for (peer_id, proto_id) in dropped {
+            self.metrics.channel_overflow();
+            self.metrics.traffic().record_dropped();
+            self.peer_message_dropped(peer_id, proto_id, DropReason::ServiceBusy);
+        }
+        // More messages are dropped by the handle
+        if !self.service_context.dropped_messages.is_empty()
+            || !self.service_context.dropped_peer_messages.is_empty()
+        {
+            task::current().notify();
+        }
     }
 
     /// Get the callback handle of the specified protocol
@@ -450,6 +1154,8 @@ where
     ) -> Option<Box<dyn ProtocolHandle + Send + 'static>> {
         let handle = self
             .protocol_configs
+            .read()
+            .unwrap()
             .values()
             .map(|proto| {
                 if proto.id() == proto_id {
@@ -478,79 +1184,391 @@ where
         }
     }
 
-    /// Handshake
-    #[inline]
-    fn handshake(&mut self, socket: TcpStream, ty: SessionType) {
-        let address = socket.peer_addr().unwrap();
-        if let Some(ref key_pair) = self.key_pair {
-            let key_pair = key_pair.clone();
-            let mut success_sender = self.session_event_sender.clone();
-            let mut fail_sender = self.session_event_sender.clone();
-
-            let task = Config::new(key_pair)
-                .handshake(socket)
-                .and_then(move |(handle, public_key, _)| {
-                    let _ = success_sender.try_send(SessionEvent::HandshakeSuccess {
-                        handle,
-                        public_key,
-                        address,
-                        ty,
-                    });
-                    Ok(())
-                })
-                .timeout(Duration::from_secs(10))
-                .map_err(move |err| {
-                    error!(
-                        "Handshake with {} failed, error: {:?}",
-                        address,
-                        err.description()
-                    );
-                    let _ = fail_sender.try_send(SessionEvent::HandshakeFail {
-                        ty,
-                        error: io::Error::new(io::ErrorKind::TimedOut, err.description()),
-                    });
-                });
-
-            tokio::spawn(task);
-        } else {
-            self.session_open(socket, None, address, ty);
-            if ty == SessionType::Client {
-                self.task_count -= 1;
-            }
+    /// Dial the address if it's not being dialed
+    fn dial_inner(&mut self, address: Multiaddr, mut options: DialOptions) {
+        if self.shutdown.is_some() {
+            self.dial_cancelled(address, options.tag);
+            return;
+        }
+        if let Some(Protocol::P2p(data)) = address.protocols().last() {
+            let error = match PeerId::from_bytes(data.clone()) {
+                Ok(peer_id) => match options.peer_id {
+                    Some(ref expected) if *expected != peer_id => {
+                        Some("the peer id doesn't match the address")
+                    }
+                    Some(_) => None,
+                    None => {
+                        options.peer_id = Some(peer_id);
+                        None
+                    }
+                },
+                Err(_) => Some("invalid peer id in the address"),
+            };
+            if let Some(error) = error {
+                debug!("dial {} failed, {}", address, error);
+                self.metrics.dial_error();
+                self.handle.handle_error(
+                    &mut self.service_context,
+                    ServiceEvent::DialerError {
+                        address,
+                        error: io::Error::new(io::ErrorKind::InvalidInput, error),
+                        tag: options.tag,
+                    },
+                );
+                return;
+            }
+        }
+        if !self.dial.iter().any(|(addr, _, _)| addr == &address) {
+            let dial = self.dialer(address.clone(), options.timeout);
+            self.dial.push((address, options, dial));
+            self.task_count += 1;
+        } else if options.tag.is_some() {
+            // Tell the caller the result will never come
+            self.handle.handle_error(
+                &mut self.service_context,
+                ServiceEvent::DialerError {
+                    address,
+                    error: io::Error::new(io::ErrorKind::AlreadyExists, "already dialing"),
+                    tag: options.tag,
+                },
+            );
+        }
+    }
+
+    /// The outbound connection is failed
+    fn dial_failed(&mut self, address: Multiaddr, error: io::Error, tag: Option<u64>) {
+        self.dial_error(&address);
+        self.handle.handle_error(
+            &mut self.service_context,
+            ServiceEvent::DialerError {
+                address,
+                error,
+                tag,
+            },
+        );
+    }
+
+    /// The dial is abandoned because the service is shutting down, not counted as a failure
+    fn dial_cancelled(&mut self, address: Multiaddr, tag: Option<u64>) {
+        debug!("dial {} cancelled, service is shutting down", address);
+        if tag.is_some() {
+            // Tell the caller the result will never come
+            self.handle.handle_error(
+                &mut self.service_context,
+                ServiceEvent::DialerError {
+                    address,
+                    error: shutting_down(),
+                    tag,
+                },
+            );
+        }
+    }
+
+    /// Count the failed dial, redial later if it's a persistent peer
+    fn dial_error(&mut self, address: &Multiaddr) {
+        self.metrics.dial_error();
+        self.task_count -= 1;
+        self.redial_later(address);
+    }
+
+    /// Keep a connection to the address
+    pub(crate) fn add_persistent_peer(&mut self, address: Multiaddr) {
+        if self.shutdown.is_some() || self.persistent_peers.contains_key(&address) {
+            return;
+        }
+        self.persistent_peers.insert(address.clone(), 0);
+        let connected = self
+            .sessions
+            .values()
+            .any(|session| session.ty == SessionType::Client && session.address == address);
+        if !connected {
+            self.dial_inner(address, DialOptions::default());
+        }
+    }
+
+    /// Stop redialing the address
+    fn remove_persistent_peer(&mut self, address: &Multiaddr) {
+        self.persistent_peers.remove(address);
+        self.redials.retain(|(addr, _)| addr != address);
+    }
+
+    /// Schedule a redial if the address is a persistent peer
+    fn redial_later(&mut self, address: &Multiaddr) {
+        if self.redials.iter().any(|(addr, _)| addr == address) {
+            return;
+        }
+        if let Some(attempts) = self.persistent_peers.get_mut(address) {
+            let delay = backoff(*attempts);
+            *attempts += 1;
+            debug!("redial {} after {:?}", address, delay);
+            self.redials
+                .push((address.clone(), Delay::new(Instant::now() + delay)));
+        }
+    }
+
+    /// Dial with the transport, refuse the banned address
+    fn dialer(&self, address: Multiaddr, timeout: Duration) -> Dialer {
+        if self.bans.is_address_banned(&address) {
+            Box::new(future::err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("{} is banned", address),
+            )))
+        } else {
+            Box::new(
+                self.transport
+                    .dial(address)
+                    .timeout(timeout)
+                    .map_err(|err| {
+                        err.into_inner()
+                            .unwrap_or_else(|| io::ErrorKind::TimedOut.into())
+                    }),
+            )
+        }
+    }
+
+    /// Ban the target, close the sessions with it
+    fn ban(&mut self, target: BanTarget, duration: Duration) {
+        debug!("ban {:?} for {:?}", target, duration);
+        let ids = self
+            .sessions
+            .iter()
+            .filter(|(id, session)| {
+                target.match_address(&session.address)
+                    || self
+                        .remote_pubkeys
+                        .get(id)
+                        .is_some_and(|key| target.match_public_key(key))
+            })
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        // An overflowing duration, like `u64::MAX` seconds, bans forever
+        self.bans
+            .insert(target, Instant::now().checked_add(duration));
+        self.service_context.update_bans(self.bans.clone());
+        ids.into_iter()
+            .for_each(|id| self.session_close(id, CloseReason::LocalDisconnect));
+    }
+
+    /// Stop listening and dialing, close all sessions after flushing
+    fn shutdown(&mut self) {
+        if self.shutdown.is_some() {
+            return;
+        }
+        debug!("service shutting down");
+        self.shutdown = Some(Delay::new(Instant::now() + self.config.shutdown_timeout));
+
+        self.listens.clear();
+        self.service_context.update_listens(Vec::new());
+        self.persistent_peers.clear();
+        self.redials.clear();
+        for (address, options, _) in self.dial.split_off(0) {
+            self.task_count -= 1;
+            self.dial_cancelled(address, options.tag);
+        }
+        if self.forever {
+            self.task_count -= 1;
+        }
+
+        for session in self.sessions.values_mut() {
+            let _ = session.sender.try_send(SessionEvent::Shutdown);
+        }
+    }
+
+    /// Clean up the expired bans
+    fn clear_expired_bans(&mut self) {
+        if self.bans.clear_expired(Instant::now()) {
+            self.service_context.update_bans(self.bans.clone());
+        }
+    }
+
+    /// Find the limit exceeded if a new connection is accepted
+    fn exceeded_limit(&self, address: &Multiaddr, ty: SessionType) -> Option<ConnectionLimit> {
+        let exceeded = |max: Option<usize>, count: usize| max.is_some_and(|max| count >= max);
+        let connections = self
+            .sessions
+            .values()
+            .map(|session| (&session.address, session.ty))
+            .chain(self.handshakes.iter().map(|(address, ty)| (address, *ty)))
+            .collect::<Vec<_>>();
+
+        if exceeded(self.limits.max_sessions, connections.len()) {
+            return Some(ConnectionLimit::Sessions);
+        }
+
+        let same_type = connections.iter().filter(|(_, t)| *t == ty).count();
+        match ty {
+            SessionType::Server if exceeded(self.limits.max_inbound, same_type) => {
+                return Some(ConnectionLimit::Inbound);
+            }
+            SessionType::Client if exceeded(self.limits.max_outbound, same_type) => {
+                return Some(ConnectionLimit::Outbound);
+            }
+            _ => (),
+        }
+
+        if let Some(ip) = multiaddr_to_ip(address) {
+            let same_ip = connections
+                .iter()
+                .filter(|(address, _)| multiaddr_to_ip(address) == Some(ip))
+                .count();
+            if exceeded(self.limits.max_per_ip, same_ip) {
+                return Some(ConnectionLimit::PerIp);
+            }
+        }
+        None
+    }
+
+    /// Handshake
+    #[inline]
+    fn handshake(
+        &mut self,
+        socket: BoxedConnection,
+        address: Multiaddr,
+        ty: SessionType,
+        dial: Option<DialOptions>,
+    ) {
+        // Drop the connection before spending anything on it
+        if let Some(limit) = self.exceeded_limit(&address, ty) {
+            debug!("reject {:?} connection {}, {:?} limit", ty, address, limit);
+            if ty == SessionType::Client {
+                self.task_count -= 1;
+                self.redial_later(&address);
+            }
+            self.handle.handle_event(
+                &mut self.service_context,
+                ServiceEvent::ConnectionRejected {
+                    address,
+                    ty,
+                    limit,
+                    tag: dial.and_then(|dial| dial.tag),
+                },
+            );
+            return;
+        }
+
+        if let Some(ref key_pair) = self.key_pair {
+            let key_pair = key_pair.clone();
+            let event_sender = self.session_event_sender.clone();
+            self.handshakes.push((address.clone(), ty));
+
+            let task = self
+                .config
+                .secio(key_pair)
+                .handshake(socket)
+                .timeout(self.config.handshake_timeout)
+                .then(move |result| {
+                    let event = match result {
+                        Ok((handle, public_key, _)) => SessionEvent::HandshakeSuccess {
+                            handle,
+                            public_key,
+                            address,
+                            ty,
+                            dial,
+                        },
+                        Err(err) => {
+                            error!(
+                                "Handshake with {} failed, error: {:?}",
+                                address,
+                                err.description()
+                            );
+                            SessionEvent::HandshakeFail {
+                                address,
+                                ty,
+                                error: io::Error::new(io::ErrorKind::TimedOut, err.description()),
+                                dial,
+                            }
+                        }
+                    };
+                    // Wait for room, the handshake holds a connection limit slot until the
+                    // service receives its result
+                    event_sender.send(event).map(|_| ()).map_err(|_| ())
+                });
+
+            tokio::spawn(task);
+        } else {
+            let (tag, expected) = match dial {
+                Some(dial) => (dial.tag, dial.peer_id.is_some()),
+                None => (None, false),
+            };
+            if expected {
+                let error = io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "can't verify public key without secio",
+                );
+                self.dial_failed(address, error, tag);
+                return;
+            }
+            self.session_open(socket, None, address, ty, tag, None, None, None);
+            if ty == SessionType::Client {
+                self.task_count -= 1;
+            }
         }
     }
 
     /// Session open
     #[inline]
+    #[allow(clippy::too_many_arguments)]
     fn session_open<H>(
         &mut self,
         mut handle: H,
         public_key: Option<PublicKey>,
-        address: SocketAddr,
+        address: Multiaddr,
         ty: SessionType,
+        tag: Option<u64>,
+        secure_traffic: Option<Arc<TrafficStats>>,
+        negotiated: Option<Negotiated>,
+        nonces: Option<Vec<u8>>,
     ) where
         H: AsyncRead + AsyncWrite + Send + 'static,
     {
+        let mut duplicate = None;
         if let Some(ref key) = public_key {
-            // If the public key exists, the connection has been established
-            // and then the useless connection needs to be closed.
-            if self
+            // If the public key exists, the connection has been established,
+            // only one of the two connections is kept
+            let existing = self
                 .remote_pubkeys
-                .values()
-                .any(|current_key| current_key == key)
+                .iter()
+                .find(|(_, current_key)| *current_key == key)
+                .map(|(id, _)| *id);
+            if let Some((id, existing)) =
+                existing.and_then(|id| self.sessions.get(&id).map(|session| (id, session)))
             {
-                let _ = handle.shutdown();
-            } else {
-                self.next_session += 1;
-                self.remote_pubkeys.insert(self.next_session, key.clone());
+                if !self.keep_new_connection(key, existing, ty, nonces.as_ref()) {
+                    debug!("close duplicate connection with {}", address);
+                    let _ = handle.shutdown();
+                    let kept_ty = existing.ty;
+                    self.handle.handle_event(
+                        &mut self.service_context,
+                        ServiceEvent::DuplicateConnection {
+                            peer_id: key.peer_id(),
+                            kept: id,
+                            kept_ty,
+                            closed_address: address,
+                            closed_ty: ty,
+                            closed_tag: tag,
+                        },
+                    );
+                    return;
+                }
+                debug!("close duplicate session [{}] with {}", id, existing.address);
+                duplicate = Some((existing.address.clone(), existing.ty));
+                self.session_close(id, CloseReason::LocalDisconnect);
             }
+            self.next_session += 1;
+            self.remote_pubkeys.insert(self.next_session, key.clone());
+            self.peer_sessions.insert(key.peer_id(), self.next_session);
         } else {
             self.next_session += 1;
         }
 
         let (service_event_sender, service_event_receiver) = mpsc::channel(256);
-        let meta = SessionMeta::new(self.next_session, ty, address, public_key.clone())
-            .protocol(self.protocol_configs.clone());
+        let traffic = Arc::new(TrafficStats::new());
+        let meta = SessionMeta::new(self.next_session, ty, address.clone(), public_key.clone())
+            .config(self.config.yamux)
+            .protocol_timeout(self.config.protocol_timeout)
+            .protocol(self.protocol_configs.clone())
+            .traffic(traffic.clone())
+            .metrics(self.metrics.clone());
         let mut session = Session::new(
             handle,
             self.session_event_sender.clone(),
@@ -559,38 +1577,140 @@ where
         );
 
         if ty == SessionType::Client {
-            self.protocol_configs
-                .keys()
+            let names = self
+                .protocol_configs
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|(_, proto)| proto.open_on_connect())
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>();
+            names
+                .iter()
                 .for_each(|name| session.open_proto_stream(name));
         }
-        self.sessions
-            .insert(self.next_session, service_event_sender);
+        self.sessions.insert(
+            self.next_session,
+            SessionController {
+                sender: service_event_sender,
+                address: address.clone(),
+                ty,
+                nonces,
+            },
+        );
+        let peer_id = public_key.as_ref().map(PublicKey::peer_id);
+        self.service_context.sessions.insert(
+            self.next_session,
+            SessionInfo {
+                id: self.next_session,
+                address: address.clone(),
+                public_key: public_key.clone(),
+                peer_id: peer_id.clone(),
+                ty,
+                negotiated,
+                protocols: HashMap::new(),
+                connected_at: Instant::now(),
+                traffic,
+                secure_traffic,
+                protocol_traffic: HashMap::new(),
+            },
+        );
+        self.metrics.session_opened();
 
         tokio::spawn(session.for_each(|_| Ok(())).map_err(|_| ()));
 
+        if ty == SessionType::Client {
+            if let Some(attempts) = self.persistent_peers.get_mut(&address) {
+                *attempts = 0;
+            }
+        }
+
         self.handle.handle_event(
             &mut self.service_context,
             ServiceEvent::SessionOpen {
                 id: self.next_session,
                 address,
-                ty: SessionType::Server,
+                ty,
                 public_key,
+                peer_id: peer_id.clone(),
+                negotiated,
+                tag,
             },
         );
+
+        if let (Some((closed_address, closed_ty)), Some(peer_id)) = (duplicate, peer_id) {
+            self.handle.handle_event(
+                &mut self.service_context,
+                ServiceEvent::DuplicateConnection {
+                    peer_id,
+                    kept: self.next_session,
+                    kept_ty: ty,
+                    closed_address,
+                    closed_ty,
+                    closed_tag: None,
+                },
+            );
+        }
+    }
+
+    /// Whether to replace the existing connection with the peer by the new one,
+    /// both sides make the same choice
+    fn keep_new_connection(
+        &self,
+        remote_public_key: &PublicKey,
+        existing: &SessionController,
+        new: SessionType,
+        nonces: Option<&Vec<u8>>,
+    ) -> bool {
+        if existing.ty == new {
+            // Both dialed by the same side, keep the connection with the higher nonces
+            return match (existing.nonces.as_ref(), nonces) {
+                (Some(existing), Some(new)) => new > existing,
+                _ => false,
+            };
+        }
+        match self.key_pair {
+            // Keep the connection dialed by the lower peer id
+            Some(ref key_pair) => {
+                let local_is_lower = key_pair.peer_id() < remote_public_key.peer_id();
+                (new == SessionType::Client) == local_is_lower
+            }
+            None => false,
+        }
+    }
+
+    /// The session with the peer
+    fn peer_session(&self, peer_id: &PeerId) -> Option<SessionId> {
+        self.peer_sessions.get(peer_id).cloned()
     }
 
     /// Close the specified session, clean up the handle
     #[inline]
-    fn session_close(&mut self, id: SessionId) {
+    fn session_close(&mut self, id: SessionId, reason: CloseReason) {
         debug!("service session [{}] close", id);
-        self.remote_pubkeys.remove(&id);
-        if let Some(mut session_sender) = self.sessions.remove(&id) {
-            let _ = session_sender.try_send(SessionEvent::SessionClose { id });
+        if let Some(key) = self.remote_pubkeys.remove(&id) {
+            self.peer_sessions.remove(&key.peer_id());
+        }
+        self.service_context.sessions.remove(&id);
+        let mut session = match self.sessions.remove(&id) {
+            Some(session) => session,
+            // Already closed
+            None => return,
+        };
+        self.metrics.session_closed();
+        let _ = session.sender.try_send(SessionEvent::SessionClose {
+            id,
+            reason: CloseReason::LocalDisconnect,
+        });
+        if session.ty == SessionType::Client {
+            self.redial_later(&session.address);
         }
 
         // Service handle processing flow
-        self.handle
-            .handle_event(&mut self.service_context, ServiceEvent::SessionClose { id });
+        self.handle.handle_event(
+            &mut self.service_context,
+            ServiceEvent::SessionClose { id, reason },
+        );
 
         // Session proto handle processing flow
         let mut close_proto_ids = Vec::new();
@@ -612,6 +1732,7 @@ where
             if let Some(handle) = self.proto_handles.get_mut(&proto_id) {
                 handle.disconnected(&mut self.service_context, id);
             }
+            self.send_channel_event(ChannelEvent::ProtocolClose { id, proto_id });
         });
     }
 
@@ -621,21 +1742,28 @@ where
         &mut self,
         id: SessionId,
         proto_id: ProtocolId,
-        address: SocketAddr,
+        address: &Multiaddr,
         ty: SessionType,
         remote_public_key: &Option<PublicKey>,
         version: &str,
     ) {
         debug!("service session [{}] proto [{}] open", id, proto_id);
+        self.metrics.protocol_opened();
+        let mut peer_id = None;
+        if let Some(info) = self.service_context.sessions.get_mut(&id) {
+            info.protocols.insert(proto_id, version.to_owned());
+            peer_id = info.peer_id.clone();
+        }
 
         // Global proto handle processing flow
         if let Some(handle) = self.proto_handles.get_mut(&proto_id) {
             handle.connected(
                 &mut self.service_context,
                 id,
-                address,
+                address.clone(),
                 ty,
                 &remote_public_key,
+                peer_id.as_ref(),
                 &version,
             );
         } else if let Some(mut handle) = self.get_proto_handle(false, proto_id) {
@@ -643,9 +1771,10 @@ where
             handle.connected(
                 &mut self.service_context,
                 id,
-                address,
+                address.clone(),
                 ty,
                 &remote_public_key,
+                peer_id.as_ref(),
                 &version,
             );
             self.proto_handles.insert(proto_id, handle);
@@ -661,9 +1790,10 @@ where
                 handle.connected(
                     &mut self.service_context,
                     id,
-                    address,
+                    address.clone(),
                     ty,
                     &remote_public_key,
+                    peer_id.as_ref(),
                     &version,
                 );
                 Some(handle)
@@ -675,6 +1805,16 @@ where
             .entry(id)
             .or_default()
             .insert(proto_id, session_level_handle);
+
+        self.send_channel_event(ChannelEvent::ProtocolOpen {
+            id,
+            proto_id,
+            address: address.clone(),
+            ty,
+            public_key: remote_public_key.clone(),
+            peer_id,
+            version: version.to_owned(),
+        });
     }
 
     /// Processing the received data
@@ -693,6 +1833,7 @@ where
                     id,
                     proto_id,
                     data: data.to_vec(),
+                    priority: Priority::Normal,
                 },
             );
         }
@@ -706,16 +1847,29 @@ where
                         id,
                         proto_id,
                         data: data.to_vec(),
+                        priority: Priority::Normal,
                     },
                 );
             }
         }
+
+        self.send_channel_event(ChannelEvent::Message(Message {
+            id,
+            proto_id,
+            data: data.to_vec(),
+            priority: Priority::Normal,
+        }));
     }
 
     /// Protocol stream is closed, clean up data
     #[inline]
     fn protocol_close(&mut self, id: SessionId, proto_id: ProtocolId) {
         debug!("service session [{}] proto [{}] close", id, proto_id);
+        self.metrics.protocol_closed();
+        if let Some(info) = self.service_context.sessions.get_mut(&id) {
+            info.protocols.remove(&proto_id);
+            info.protocol_traffic.remove(&proto_id);
+        }
 
         // Global proto handle processing flow
         if let Some(handle) = self.proto_handles.get_mut(&proto_id) {
@@ -728,31 +1882,153 @@ where
                 handle.disconnected(&mut self.service_context, id);
             }
         }
+
+        self.drop_unregistered_handle(proto_id);
+
+        self.send_channel_event(ChannelEvent::ProtocolClose { id, proto_id });
+    }
+
+    /// Drop the global handle of an unregistered protocol after it's closed on all sessions
+    fn drop_unregistered_handle(&mut self, proto_id: ProtocolId) {
+        let registered = self
+            .protocol_configs
+            .read()
+            .unwrap()
+            .values()
+            .any(|proto| proto.id() == proto_id);
+        if !registered
+            && !self
+                .service_context
+                .sessions
+                .values()
+                .any(|info| info.protocols.contains_key(&proto_id))
+        {
+            self.proto_handles.remove(&proto_id);
+        }
+    }
+
+    /// Send the event to the channel in channel mode
+    #[inline]
+    fn send_channel_event(&mut self, event: ChannelEvent) {
+        if let Some(ref sender) = self.event_sender {
+            sender.lock().unwrap().send(event);
+        }
+    }
+
+    /// The connection is no longer in handshake
+    fn handshake_finished(&mut self, address: &Multiaddr, ty: SessionType) {
+        if let Some(index) = self
+            .handshakes
+            .iter()
+            .position(|(addr, t)| addr == address && *t == ty)
+        {
+            self.handshakes.remove(index);
+        }
     }
 
     /// Handling various events uploaded by the session
     fn handle_session_event(&mut self, event: SessionEvent) {
         match event {
-            SessionEvent::SessionClose { id } => self.session_close(id),
+            SessionEvent::SessionClose { id, reason } => self.session_close(id, reason),
             SessionEvent::HandshakeSuccess {
                 handle,
                 public_key,
                 address,
                 ty,
+                dial,
             } => {
-                self.session_open(handle, Some(public_key), address, ty);
-                if ty == SessionType::Client {
-                    self.task_count -= 1;
+                self.handshake_finished(&address, ty);
+                self.metrics.handshake_finished(true);
+                let (tag, expected) = match dial {
+                    Some(dial) => (dial.tag, dial.peer_id),
+                    None => (None, None),
+                };
+                let error = if self.shutdown.is_some() {
+                    Some("service is shutting down")
+                } else if self.bans.is_public_key_banned(&public_key) {
+                    Some("public key is banned")
+                } else {
+                    None
+                };
+
+                if let Some(expected) =
+                    expected.filter(|peer_id| !peer_id.is_public_key(&public_key))
+                {
+                    debug!("close session with {}, unexpected peer", address);
+                    let mut handle = handle;
+                    let _ = handle.shutdown();
+                    self.dial_error(&address);
+                    self.handle.handle_error(
+                        &mut self.service_context,
+                        ServiceEvent::UnexpectedPeer {
+                            address,
+                            expected,
+                            actual: public_key.peer_id(),
+                            tag,
+                        },
+                    );
+                    return;
+                }
+
+                match error {
+                    Some(error) => {
+                        debug!("close session with {}, {}", address, error);
+                        let mut handle = handle;
+                        let _ = handle.shutdown();
+                        if ty == SessionType::Client {
+                            if self.shutdown.is_some() {
+                                self.task_count -= 1;
+                                self.dial_cancelled(address, tag);
+                            } else {
+                                let error = io::Error::new(io::ErrorKind::PermissionDenied, error);
+                                self.dial_failed(address, error, tag);
+                            }
+                        }
+                    }
+                    None => {
+                        let secure_traffic = Some(handle.stats().clone());
+                        let negotiated = handle.negotiated();
+                        let nonces = handle.nonces().map(<[u8]>::to_vec);
+                        self.session_open(
+                            handle,
+                            Some(public_key),
+                            address,
+                            ty,
+                            tag,
+                            secure_traffic,
+                            negotiated,
+                            nonces,
+                        );
+                        if ty == SessionType::Client {
+                            self.task_count -= 1;
+                        }
+                    }
                 }
             }
-            SessionEvent::HandshakeFail { ty, .. } => {
+            SessionEvent::HandshakeFail {
+                address,
+                ty,
+                error,
+                dial,
+            } => {
+                self.handshake_finished(&address, ty);
+                self.metrics.handshake_finished(false);
                 if ty == SessionType::Client {
-                    self.task_count -= 1;
+                    self.dial_error(&address);
                 }
+                self.handle.handle_error(
+                    &mut self.service_context,
+                    ServiceEvent::HandshakeError {
+                        address,
+                        ty,
+                        error,
+                        tag: dial.and_then(|dial| dial.tag),
+                    },
+                );
             }
-            SessionEvent::ProtocolMessage { id, proto_id, data } => {
-                self.protocol_message(id, proto_id, &data)
-            }
+            SessionEvent::ProtocolMessage {
+                id, proto_id, data, ..
+            } => self.protocol_message(id, proto_id, &data),
             SessionEvent::ProtocolOpen {
                 id,
                 proto_id,
@@ -760,16 +2036,31 @@ where
                 remote_public_key,
                 ty,
                 version,
+                traffic,
                 ..
-            } => self.protocol_open(
+            } => {
+                if let Some(info) = self.service_context.sessions.get_mut(&id) {
+                    info.protocol_traffic.insert(proto_id, traffic);
+                }
+                self.protocol_open(
+                    id,
+                    proto_id,
+                    &remote_address,
+                    ty,
+                    &remote_public_key,
+                    &version,
+                )
+            }
+            SessionEvent::ProtocolClose { id, proto_id, .. } => self.protocol_close(id, proto_id),
+            SessionEvent::MessageDropped {
                 id,
                 proto_id,
-                remote_address,
-                ty,
-                &remote_public_key,
-                &version,
-            ),
-            SessionEvent::ProtocolClose { id, proto_id, .. } => self.protocol_close(id, proto_id),
+                reason,
+            } => self.message_dropped(id, proto_id, reason),
+            // Only sent by service
+            SessionEvent::Shutdown
+            | SessionEvent::OpenProtocol { .. }
+            | SessionEvent::CloseProtocol { .. } => (),
         }
     }
 
@@ -777,13 +2068,64 @@ where
     fn handle_service_task(&mut self, event: ServiceTask) {
         match event {
             ServiceTask::ProtocolMessage { ids, message } => self.filter_broadcast(ids, message),
-            ServiceTask::Dial { address } => {
-                if !self.dial.iter().any(|(addr, _)| addr == &address) {
-                    let dial = TcpStream::connect(&address);
-                    self.dial.push((address, dial));
+            ServiceTask::Dial { address, options } => self.dial_inner(address, options),
+            ServiceTask::PeerMessage {
+                peer_id,
+                mut message,
+            } => match self.peer_session(&peer_id) {
+                Some(id) => {
+                    message.id = id;
+                    self.filter_broadcast(Some(vec![id]), message)
+                }
+                None => {
+                    self.metrics.traffic().record_dropped();
+                    self.peer_message_dropped(
+                        peer_id,
+                        message.proto_id,
+                        DropReason::PeerNotConnected,
+                    );
+                }
+            },
+            ServiceTask::Disconnect { id } => self.session_close(id, CloseReason::LocalDisconnect),
+            ServiceTask::DisconnectPeer { peer_id } => {
+                if let Some(id) = self.peer_session(&peer_id) {
+                    self.session_close(id, CloseReason::LocalDisconnect)
+                }
+            }
+            ServiceTask::ProtocolOpen { id, proto_id } => {
+                if let Some(session) = self.sessions.get_mut(&id) {
+                    let _ = session
+                        .sender
+                        .try_send(SessionEvent::OpenProtocol { proto_id });
+                }
+            }
+            ServiceTask::ProtocolClose { id, proto_id } => {
+                if let Some(session) = self.sessions.get_mut(&id) {
+                    let _ = session
+                        .sender
+                        .try_send(SessionEvent::CloseProtocol { proto_id });
+                }
+            }
+            ServiceTask::Ban { target, duration } => self.ban(target, duration),
+            ServiceTask::Unban { target } => {
+                if self.bans.remove(&target) {
+                    self.service_context.update_bans(self.bans.clone());
+                }
+            }
+            ServiceTask::AddPersistentPeer { address } => self.add_persistent_peer(address),
+            ServiceTask::RemovePersistentPeer { address } => self.remove_persistent_peer(&address),
+            ServiceTask::Shutdown => self.shutdown(),
+            ServiceTask::ProtocolRegistered { .. } => self.update_proto_infos(),
+            ServiceTask::ProtocolUnregistered { proto_id } => {
+                self.update_proto_infos();
+                // Not open on any session, no close event comes to drop the handle
+                self.drop_unregistered_handle(proto_id);
+                for session in self.sessions.values_mut() {
+                    let _ = session
+                        .sender
+                        .try_send(SessionEvent::CloseProtocol { proto_id });
                 }
             }
-            ServiceTask::Disconnect { id } => self.session_close(id),
             ServiceTask::FutureTask { task } => {
                 tokio::spawn(task);
             }
@@ -809,23 +2151,40 @@ where
     /// Poll client requests
     #[inline]
     fn client_poll(&mut self) {
-        for (address, mut dialer) in self.dial.split_off(0) {
+        for (address, options, mut dialer) in self.dial.split_off(0) {
             match dialer.poll() {
                 Ok(Async::Ready(socket)) => {
-                    self.handshake(socket, SessionType::Client);
+                    self.handshake(socket, address, SessionType::Client, Some(options));
                 }
                 Ok(Async::NotReady) => {
                     trace!("client not ready");
-                    self.dial.push((address, dialer));
+                    self.dial.push((address, options, dialer));
                 }
-                Err(err) => {
-                    self.task_count -= 1;
-                    self.handle.handle_error(
+                Err(err) => self.dial_failed(address, err, options.tag),
+            }
+        }
+    }
+
+    /// Poll the redial timers of persistent peers
+    #[inline]
+    fn redial_poll(&mut self) {
+        for (address, mut delay) in self.redials.split_off(0) {
+            match delay.poll() {
+                Ok(Async::NotReady) => self.redials.push((address, delay)),
+                result => {
+                    if let Err(err) = result {
+                        warn!("redial timer error: {:?}", err);
+                    }
+                    let attempt = match self.persistent_peers.get(&address) {
+                        Some(attempts) => *attempts,
+                        None => continue,
+                    };
+                    self.dial_inner(address.clone(), DialOptions::default());
+                    // The new dialer is polled in the next round
+                    task::current().notify();
+                    self.handle.handle_event(
                         &mut self.service_context,
-                        ServiceEvent::DialerError {
-                            address,
-                            error: err,
-                        },
+                        ServiceEvent::Reconnect { address, attempt },
                     );
                 }
             }
@@ -837,8 +2196,12 @@ where
     fn listen_poll(&mut self) {
         for (address, mut listen) in self.listens.split_off(0) {
             match listen.poll() {
-                Ok(Async::Ready(Some(socket))) => {
-                    self.handshake(socket, SessionType::Server);
+                Ok(Async::Ready(Some((socket, remote_address)))) => {
+                    if self.bans.is_address_banned(&remote_address) {
+                        debug!("drop connection from banned address {}", remote_address);
+                    } else {
+                        self.handshake(socket, remote_address, SessionType::Server, None);
+                    }
                     self.listens.push((address, listen));
                 }
                 Ok(Async::Ready(None)) => (),
@@ -847,7 +2210,7 @@ where
                 }
                 Err(err) => {
                     // TODO: need push back?
-                    self.listens.push((address, listen));
+                    self.listens.push((address.clone(), listen));
                     self.handle.handle_error(
                         &mut self.service_context,
                         ServiceEvent::ListenError {
@@ -859,26 +2222,50 @@ where
             }
         }
 
-        self.service_context
-            .update_listens(self.listens.iter().map(|(address, _)| *address).collect());
+        self.service_context.update_listens(
+            self.listens
+                .iter()
+                .map(|(address, _)| address.clone())
+                .collect(),
+        );
     }
 }
 
-impl<T, U> Stream for Service<T, U>
+impl<T> Stream for Service<T>
 where
     T: ServiceHandle,
-    U: Decoder<Item = bytes::BytesMut> + Encoder<Item = bytes::Bytes> + Send + 'static,
-    <U as Decoder>::Error: error::Error + Into<io::Error>,
-    <U as Encoder>::Error: error::Error + Into<io::Error>,
 {
     type Item = ();
     type Error = ();
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        if self.listens.is_empty() && self.task_count == 0 && self.sessions.is_empty() {
+        if self.listens.is_empty()
+            && self.task_count == 0
+            && self.sessions.is_empty()
+            && self.persistent_peers.is_empty()
+        {
+            return Ok(Async::Ready(None));
+        }
+
+        let timeout = match self.shutdown {
+            Some(ref mut deadline) => !matches!(deadline.poll(), Ok(Async::NotReady)),
+            None => false,
+        };
+        if timeout {
+            debug!("shutdown timeout, close all sessions");
+            let ids = self.sessions.keys().cloned().collect::<Vec<_>>();
+            ids.into_iter()
+                .for_each(|id| self.session_close(id, CloseReason::LocalDisconnect));
             return Ok(Async::Ready(None));
         }
 
+        self.clear_expired_bans();
+
+        // The events waiting for room in the channel
+        if let Some(ref sender) = self.event_sender {
+            sender.lock().unwrap().flush();
+        }
+
         self.client_poll();
 
         self.listen_poll();
@@ -907,8 +2294,17 @@ where
             }
         }
 
+        self.context_dropped();
+
+        // Poll after all the redials of this round are scheduled
+        self.redial_poll();
+
         // Double check service state
-        if self.listens.is_empty() && self.task_count == 0 && self.sessions.is_empty() {
+        if self.listens.is_empty()
+            && self.task_count == 0
+            && self.sessions.is_empty()
+            && self.persistent_peers.is_empty()
+        {
             return Ok(Async::Ready(None));
         }
         debug!(
@@ -921,3 +2317,1581 @@ where
         Ok(Async::NotReady)
     }
 }
+
+/// Negotiation infos of the protocols
+fn proto_infos(
+    protocol_configs: &HashMap<String, Box<dyn ProtocolMeta + Send + Sync>>,
+) -> HashMap<ProtocolId, ProtocolInfo> {
+    protocol_configs
+        .values()
+        .map(|meta| {
+            let proto_info = ProtocolInfo::new(&meta.name(), meta.support_versions());
+            (meta.id(), proto_info)
+        })
+        .collect()
+}
+
+fn shutting_down() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "service is shutting down")
+}
+
+/// Jittered exponential backoff, the delay is in `[d / 2, d]` where `d` doubles for each failure
+fn backoff(failures: u32) -> Duration {
+    let max = MAX_BACKOFF.as_millis() as u64;
+    let delay = min(
+        max,
+        (INITIAL_BACKOFF.as_millis() as u64) << min(failures, 16),
+    );
+    Duration::from_millis(rand::thread_rng().gen_range(delay / 2, delay + 1))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{
+        backoff, CloseReason, ConnectionLimit, DialOptions, DropReason, Message, ProtocolHandle,
+        ProtocolRegistry, Service, ServiceContext, ServiceEvent, ServiceHandle, SessionController,
+        SessionInfo, MAX_BACKOFF,
+    };
+    use crate::{
+        builder::ServiceBuilder,
+        channel::{ChannelEvent, ServiceControl},
+        codec::Codec,
+        metrics::Metrics,
+        multiaddr::{Multiaddr, Protocol},
+        session::{ProtocolId, ProtocolMeta, SessionId},
+        transport::{Dialer, Incoming, MemoryTransport, Transport},
+        Cipher, PeerId, PublicKey, SecioKeyPair, SessionType, YamuxConfig,
+    };
+    use futures::{future, prelude::*, stream::Wait, sync::mpsc};
+    use std::{
+        collections::HashSet,
+        io,
+        sync::mpsc::{channel, Receiver, Sender},
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+    use tokio::codec::{length_delimited::LengthDelimitedCodec, BytesCodec};
+
+    /// Protocol 1 without handle
+    pub(crate) struct TestProtocol;
+
+    impl ProtocolMeta for TestProtocol {
+        fn id(&self) -> ProtocolId {
+            1
+        }
+        fn codec(&self) -> Box<dyn Codec> {
+            Box::new(LengthDelimitedCodec::new())
+        }
+    }
+
+    struct SHandle {
+        sender: Sender<ServiceEvent>,
+        /// Ban every peer forever once the session is open
+        ban: bool,
+    }
+
+    impl ServiceHandle for SHandle {
+        fn handle_error(&mut self, _control: &mut ServiceContext, error: ServiceEvent) {
+            let _ = self.sender.send(error);
+        }
+
+        fn handle_event(&mut self, control: &mut ServiceContext, event: ServiceEvent) {
+            if let ServiceEvent::SessionOpen {
+                public_key: Some(ref key),
+                ..
+            } = event
+            {
+                if self.ban {
+                    control.ban(key.clone(), Duration::from_secs(u64::MAX));
+                }
+            }
+            let _ = self.sender.send(event);
+        }
+    }
+
+    /// Builder with `TestProtocol`, a new key pair and the memory transport
+    pub(crate) fn builder(transport: &MemoryTransport) -> ServiceBuilder {
+        builder_with_key(transport, SecioKeyPair::secp256k1_generated())
+    }
+
+    fn builder_with_key(transport: &MemoryTransport, key_pair: SecioKeyPair) -> ServiceBuilder {
+        ServiceBuilder::default()
+            .insert_protocol(TestProtocol)
+            .key_pair(key_pair)
+            .transport(transport.clone())
+    }
+
+    fn run(service: Service<SHandle>) {
+        thread::spawn(|| tokio::run(service.for_each(|_| Ok(()))));
+    }
+
+    /// A running service in channel mode
+    pub(crate) struct ChannelEnd {
+        pub(crate) control: ServiceControl,
+        pub(crate) registry: ProtocolRegistry,
+        pub(crate) metrics: Arc<Metrics>,
+        pub(crate) events: Wait<mpsc::Receiver<ChannelEvent>>,
+    }
+
+    /// Run the services of the builders in channel mode, the client dials the server
+    pub(crate) fn connect_channels(
+        server: ServiceBuilder,
+        client: ServiceBuilder,
+    ) -> (ChannelEnd, ChannelEnd) {
+        connect_channels_on(server, client, "/memory/server".parse().unwrap())
+    }
+
+    /// Wait for the session open event, return the remote address
+    pub(crate) fn session_address(end: &mut ChannelEnd) -> Multiaddr {
+        end.events
+            .find_map(|event| match event {
+                Ok(ChannelEvent::Service(ServiceEvent::SessionOpen { address, .. })) => {
+                    Some(address)
+                }
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    /// Same as `connect_channels`, the server listens on the address
+    pub(crate) fn connect_channels_on(
+        server: ServiceBuilder,
+        client: ServiceBuilder,
+        address: Multiaddr,
+    ) -> (ChannelEnd, ChannelEnd) {
+        let (mut server, server_events) = server.forever(true).build_channel();
+        let address = server.listen(address).unwrap();
+        let (client, client_events) = client.forever(true).build_channel();
+        let server_end = ChannelEnd {
+            control: server.control(),
+            registry: server.registry(),
+            metrics: server.metrics(),
+            events: server_events.wait(),
+        };
+        let client_end = ChannelEnd {
+            control: client.control(),
+            registry: client.registry(),
+            metrics: client.metrics(),
+            events: client_events.wait(),
+        };
+        let client = client.dial(address);
+        thread::spawn(|| tokio::run(server.for_each(|_| Ok(()))));
+        thread::spawn(|| tokio::run(client.for_each(|_| Ok(()))));
+        (server_end, client_end)
+    }
+
+    #[test]
+    fn test_inbound_limit() {
+        let transport = MemoryTransport::new();
+        let (sender, receiver) = channel();
+        let (client_sender, _client_receiver) = channel();
+
+        let mut server = builder(&transport)
+            .max_inbound_sessions(1)
+            .build(SHandle { sender, ban: false });
+        let address: Multiaddr = server.listen("/memory/server".parse().unwrap()).unwrap();
+        run(server);
+
+        for _ in 0..2 {
+            let client = builder(&transport).build(SHandle {
+                sender: client_sender.clone(),
+                ban: false,
+            });
+            run(client.dial(address.clone()));
+        }
+
+        let mut opened = 0;
+        let mut rejected = 0;
+        while opened + rejected < 2 {
+            match receiver.recv_timeout(Duration::from_secs(10)).unwrap() {
+                ServiceEvent::SessionOpen { .. } => opened += 1,
+                ServiceEvent::ConnectionRejected { ty, limit, .. } => {
+                    assert_eq!(ty, SessionType::Server);
+                    assert_eq!(limit, ConnectionLimit::Inbound);
+                    rejected += 1;
+                }
+                _ => (),
+            }
+        }
+        assert_eq!((opened, rejected), (1, 1));
+    }
+
+    #[test]
+    fn test_outbound_limit_tag() {
+        let transport = MemoryTransport::new();
+        let (sender, receiver) = channel();
+
+        let mut client = builder(&transport)
+            .max_outbound_sessions(1)
+            .build(SHandle { sender, ban: false });
+        for i in 0..2 {
+            let (server_sender, _) = channel();
+            let mut server = builder(&transport).build(SHandle {
+                sender: server_sender,
+                ban: false,
+            });
+            let address = server
+                .listen(format!("/memory/{}", i).parse().unwrap())
+                .unwrap();
+            run(server);
+            client = client.dial_with(
+                address,
+                DialOptions {
+                    tag: Some(i),
+                    ..Default::default()
+                },
+            );
+        }
+        run(client);
+
+        let mut tags = Vec::new();
+        while tags.len() < 2 {
+            match receiver.recv_timeout(Duration::from_secs(10)).unwrap() {
+                ServiceEvent::SessionOpen { tag, .. } => tags.push(tag),
+                ServiceEvent::ConnectionRejected { limit, tag, .. } => {
+                    assert_eq!(limit, ConnectionLimit::Outbound);
+                    tags.push(tag);
+                }
+                _ => (),
+            }
+        }
+        tags.sort();
+        assert_eq!(tags, vec![Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn test_ban_closes_session() {
+        let transport = MemoryTransport::new();
+        let (sender, receiver) = channel();
+        let (client_sender, _client_receiver) = channel();
+
+        let mut server = builder(&transport).build(SHandle { sender, ban: true });
+        let address = server.listen("/memory/server".parse().unwrap()).unwrap();
+        run(server);
+        let client = builder(&transport).build(SHandle {
+            sender: client_sender,
+            ban: false,
+        });
+        run(client.dial(address));
+
+        let open_id = match receiver.recv_timeout(Duration::from_secs(10)).unwrap() {
+            ServiceEvent::SessionOpen { id, .. } => id,
+            event => panic!("unexpected event {:?}", event),
+        };
+        match receiver.recv_timeout(Duration::from_secs(10)).unwrap() {
+            ServiceEvent::SessionClose { id, reason } => {
+                assert_eq!(id, open_id);
+                assert!(matches!(reason, CloseReason::LocalDisconnect));
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert!(backoff(0) <= Duration::from_secs(1));
+        assert!(backoff(3) >= Duration::from_secs(4));
+        assert!(backoff(3) <= Duration::from_secs(8));
+        assert!(backoff(100) >= MAX_BACKOFF / 2);
+        assert!(backoff(100) <= MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_persistent_peer_reconnect() {
+        let transport = MemoryTransport::new();
+        let (sender, receiver) = channel();
+        let (server_sender, _server_receiver) = channel();
+        let address: Multiaddr = "/memory/server".parse().unwrap();
+
+        let client = builder(&transport)
+            .persistent_peer(address.clone())
+            .build(SHandle { sender, ban: false });
+        run(client);
+
+        // Nobody listens on the address yet
+        match receiver.recv_timeout(Duration::from_secs(10)).unwrap() {
+            ServiceEvent::DialerError { address: addr, .. } => assert_eq!(addr, address),
+            event => panic!("unexpected event {:?}", event),
+        }
+        match receiver.recv_timeout(Duration::from_secs(10)).unwrap() {
+            ServiceEvent::Reconnect {
+                address: addr,
+                attempt,
+            } => {
+                assert_eq!(addr, address);
+                assert_eq!(attempt, 1);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        let mut server = builder(&transport).build(SHandle {
+            sender: server_sender,
+            ban: false,
+        });
+        server.listen(address.clone()).unwrap();
+        run(server);
+
+        loop {
+            match receiver.recv_timeout(Duration::from_secs(10)).unwrap() {
+                ServiceEvent::SessionOpen { address: addr, .. } => {
+                    assert_eq!(addr, address);
+                    break;
+                }
+                ServiceEvent::Reconnect { .. } | ServiceEvent::DialerError { .. } => (),
+                event => panic!("unexpected event {:?}", event),
+            }
+        }
+    }
+
+    #[test]
+    fn test_dial_options() {
+        let transport = MemoryTransport::new();
+        let key_pair = SecioKeyPair::secp256k1_generated();
+        let (sender, receiver) = channel();
+        let (server_sender, _server_receiver) = channel();
+
+        let mut server = builder_with_key(&transport, key_pair.clone()).build(SHandle {
+            sender: server_sender,
+            ban: false,
+        });
+        let address = server.listen("/memory/server".parse().unwrap()).unwrap();
+        run(server);
+
+        let expected = DialOptions {
+            peer_id: Some(key_pair.peer_id()),
+            tag: Some(1),
+            ..Default::default()
+        };
+        let other_key = SecioKeyPair::secp256k1_generated().to_public_key();
+        let unexpected = DialOptions {
+            peer_id: Some(other_key.clone().into()),
+            tag: Some(2),
+            ..Default::default()
+        };
+        for options in [expected, unexpected].iter().cloned() {
+            let client = builder(&transport).build(SHandle {
+                sender: sender.clone(),
+                ban: false,
+            });
+            run(client.dial_with(address.clone(), options));
+        }
+
+        let mut tags = Vec::new();
+        for _ in 0..2 {
+            match receiver.recv_timeout(Duration::from_secs(10)).unwrap() {
+                ServiceEvent::SessionOpen { tag, .. } => tags.push(tag),
+                ServiceEvent::UnexpectedPeer {
+                    expected,
+                    actual,
+                    tag,
+                    ..
+                } => {
+                    assert_eq!(expected, other_key.peer_id());
+                    assert_eq!(actual, key_pair.peer_id());
+                    tags.push(tag.map(|tag| tag * 10));
+                }
+                event => panic!("unexpected event {:?}", event),
+            }
+        }
+        tags.sort();
+        assert_eq!(tags, vec![Some(1), Some(20)]);
+    }
+
+    #[test]
+    fn test_dial_peer_address() {
+        let transport = MemoryTransport::new();
+        let key_pair = SecioKeyPair::secp256k1_generated();
+        let (sender, receiver) = channel();
+        let (server_sender, _server_receiver) = channel();
+
+        let mut server = builder_with_key(&transport, key_pair.clone()).build(SHandle {
+            sender: server_sender,
+            ban: false,
+        });
+        let address = server.listen("/memory/server".parse().unwrap()).unwrap();
+        run(server);
+
+        let other_key = SecioKeyPair::secp256k1_generated().to_public_key();
+        let with_peer = |peer_id: PeerId| {
+            address
+                .clone()
+                .with(Protocol::P2p(peer_id.as_bytes().to_vec()))
+        };
+        let options = |tag, peer_id| DialOptions {
+            peer_id,
+            tag: Some(tag),
+            ..Default::default()
+        };
+        let dials = vec![
+            (with_peer(key_pair.peer_id()), options(1, None)),
+            (with_peer(other_key.peer_id()), options(2, None)),
+            (
+                with_peer(key_pair.peer_id()),
+                options(3, Some(other_key.peer_id())),
+            ),
+        ];
+        for (address, options) in dials {
+            let client = builder(&transport).build(SHandle {
+                sender: sender.clone(),
+                ban: false,
+            });
+            run(client.dial_with(address, options));
+        }
+
+        let mut tags = Vec::new();
+        for _ in 0..3 {
+            match receiver.recv_timeout(Duration::from_secs(10)).unwrap() {
+                ServiceEvent::SessionOpen { tag, .. } => tags.push(tag),
+                ServiceEvent::UnexpectedPeer { expected, tag, .. } => {
+                    assert_eq!(expected, other_key.peer_id());
+                    tags.push(tag.map(|tag| tag * 10));
+                }
+                ServiceEvent::DialerError { error, tag, .. } => {
+                    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+                    tags.push(tag.map(|tag| tag * 100));
+                }
+                event => panic!("unexpected event {:?}", event),
+            }
+        }
+        tags.sort();
+        assert_eq!(tags, vec![Some(1), Some(20), Some(300)]);
+    }
+
+    /// The client sends messages and shuts down at once
+    struct FlushProtocol {
+        sender: Arc<Mutex<Sender<String>>>,
+    }
+
+    impl ProtocolMeta for FlushProtocol {
+        fn id(&self) -> ProtocolId {
+            1
+        }
+        fn codec(&self) -> Box<dyn Codec> {
+            Box::new(LengthDelimitedCodec::new())
+        }
+        fn handle(&self) -> Option<Box<dyn ProtocolHandle + Send + 'static>> {
+            Some(Box::new(FlushHandle {
+                sender: Arc::clone(&self.sender),
+            }))
+        }
+    }
+
+    struct FlushHandle {
+        sender: Arc<Mutex<Sender<String>>>,
+    }
+
+    impl ProtocolHandle for FlushHandle {
+        fn connected(
+            &mut self,
+            control: &mut ServiceContext,
+            session_id: SessionId,
+            _address: Multiaddr,
+            ty: SessionType,
+            _remote_public_key: &Option<PublicKey>,
+            _peer_id: Option<&PeerId>,
+            _version: &str,
+        ) {
+            if ty == SessionType::Client {
+                for i in 0..5 {
+                    control.send_message(
+                        Some(vec![session_id]),
+                        Message {
+                            id: session_id,
+                            proto_id: 1,
+                            data: format!("{}", i).into_bytes(),
+                            ..Default::default()
+                        },
+                    );
+                }
+                control.shutdown();
+            }
+        }
+
+        fn received(&mut self, _control: &mut ServiceContext, data: Message) {
+            let data = String::from_utf8(data.data).unwrap();
+            let _ = self.sender.lock().unwrap().send(data);
+        }
+
+        fn disconnected(&mut self, _control: &mut ServiceContext, _session_id: SessionId) {
+            let _ = self.sender.lock().unwrap().send("disconnected".to_owned());
+        }
+    }
+
+    #[test]
+    fn test_shutdown_flushes_messages() {
+        let transport = MemoryTransport::new();
+        let (sender, receiver) = channel();
+        let (client_sender, client_receiver) = channel();
+        let (event_sender, _event_receiver) = channel();
+        let create = |sender: Sender<String>| {
+            ServiceBuilder::default()
+                .insert_protocol(FlushProtocol {
+                    sender: Arc::new(Mutex::new(sender)),
+                })
+                .key_pair(SecioKeyPair::secp256k1_generated())
+                .transport(transport.clone())
+                .forever(true)
+                .build(SHandle {
+                    sender: event_sender.clone(),
+                    ban: false,
+                })
+        };
+
+        let mut server = create(sender);
+        let address = server.listen("/memory/server".parse().unwrap()).unwrap();
+        run(server);
+        let client = create(client_sender.clone()).dial(address);
+        thread::spawn(move || {
+            tokio::run(client.for_each(|_| Ok(())));
+            let _ = client_sender.send("stopped".to_owned());
+        });
+
+        for i in 0..5 {
+            assert_eq!(
+                receiver.recv_timeout(Duration::from_secs(10)).unwrap(),
+                format!("{}", i)
+            );
+        }
+        for expected in &["disconnected", "stopped"] {
+            assert_eq!(
+                &client_receiver
+                    .recv_timeout(Duration::from_secs(10))
+                    .unwrap(),
+                expected
+            );
+        }
+    }
+
+    /// The dials never finish
+    struct PendingTransport;
+
+    impl Transport for PendingTransport {
+        fn can_handle(&self, _address: &Multiaddr) -> bool {
+            true
+        }
+
+        fn listen(&self, _address: Multiaddr) -> Result<(Multiaddr, Incoming), io::Error> {
+            Err(io::ErrorKind::Other.into())
+        }
+
+        fn dial(&self, _address: Multiaddr) -> Dialer {
+            Box::new(future::empty())
+        }
+    }
+
+    #[test]
+    fn test_shutdown_cancels_dials() {
+        let (service, events) = ServiceBuilder::default()
+            .transport(PendingTransport)
+            .forever(true)
+            .shutdown_timeout(Duration::from_secs(1))
+            .build_channel();
+        let mut control = service.control();
+        let metrics = service.metrics();
+        let options = DialOptions {
+            tag: Some(1),
+            ..Default::default()
+        };
+        let service = service
+            .dial_with("/memory/pending".parse().unwrap(), options)
+            .dial("/memory/untagged".parse().unwrap());
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            tokio::run(service.for_each(|_| Ok(())));
+            let _ = sender.send(());
+        });
+        control.shutdown().unwrap();
+
+        // Only the tagged dial is told
+        let errors = events
+            .wait()
+            .filter_map(|event| match event {
+                Ok(ChannelEvent::Error(ServiceEvent::DialerError { error, tag, .. })) => {
+                    Some((error.kind(), tag))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(errors, vec![(io::ErrorKind::Interrupted, Some(1))]);
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(metrics.render().contains("p2p_dial_errors_total 0\n"));
+    }
+
+    struct LazyProtocol(ProtocolId);
+
+    impl ProtocolMeta for LazyProtocol {
+        fn id(&self) -> ProtocolId {
+            self.0
+        }
+        fn codec(&self) -> Box<dyn Codec> {
+            Box::new(LengthDelimitedCodec::new())
+        }
+        fn open_on_connect(&self) -> bool {
+            self.0 == 1
+        }
+    }
+
+    /// Wait for the next protocol open or close event, return the protocol id and whether it's open
+    fn next_protocol_event(
+        events: &mut impl Iterator<Item = Result<ChannelEvent, ()>>,
+    ) -> (ProtocolId, bool) {
+        events
+            .find_map(|event| match event {
+                Ok(ChannelEvent::ProtocolOpen { proto_id, .. }) => Some((proto_id, true)),
+                Ok(ChannelEvent::ProtocolClose { proto_id, .. }) => Some((proto_id, false)),
+                Ok(ChannelEvent::Service(ServiceEvent::SessionClose { .. })) => {
+                    panic!("session closed")
+                }
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_open_close_protocol() {
+        let transport = MemoryTransport::new();
+        let create = || {
+            ServiceBuilder::default()
+                .insert_protocol(LazyProtocol(1))
+                .insert_protocol(LazyProtocol(2))
+                .key_pair(SecioKeyPair::secp256k1_generated())
+                .transport(transport.clone())
+        };
+        let (mut server, mut client) = connect_channels(create(), create());
+
+        let id = match client.events.next() {
+            Some(Ok(ChannelEvent::Service(ServiceEvent::SessionOpen { id, .. }))) => id,
+            event => panic!("unexpected event: {:?}", event),
+        };
+        // Only the protocol opened on connect
+        assert_eq!(next_protocol_event(&mut client.events), (1, true));
+        assert_eq!(next_protocol_event(&mut server.events), (1, true));
+
+        client.control.open_protocol(id, 2).unwrap();
+        assert_eq!(next_protocol_event(&mut client.events), (2, true));
+        assert_eq!(next_protocol_event(&mut server.events), (2, true));
+
+        // The session is kept after closing the protocol
+        client.control.close_protocol(id, 1).unwrap();
+        assert_eq!(next_protocol_event(&mut client.events), (1, false));
+        assert_eq!(next_protocol_event(&mut server.events), (1, false));
+
+        client.control.open_protocol(id, 1).unwrap();
+        assert_eq!(next_protocol_event(&mut client.events), (1, true));
+        assert_eq!(next_protocol_event(&mut server.events), (1, true));
+    }
+
+    /// Forward the channel events to a std channel, to wait for them with a timeout
+    fn forward_events(events: Wait<mpsc::Receiver<ChannelEvent>>) -> Receiver<ChannelEvent> {
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            for event in events {
+                if event.map(|event| sender.send(event)).is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+
+    /// Receive the next event, count the open and close events of the protocol,
+    /// return the message of it if any
+    fn recv_protocol(
+        events: &Receiver<ChannelEvent>,
+        proto_id: ProtocolId,
+        opened: &mut i32,
+        timeout: Duration,
+    ) -> Option<Message> {
+        match events.recv_timeout(timeout) {
+            Ok(ChannelEvent::ProtocolOpen { proto_id: id, .. }) if id == proto_id => *opened += 1,
+            Ok(ChannelEvent::ProtocolClose { proto_id: id, .. }) if id == proto_id => *opened -= 1,
+            Ok(ChannelEvent::Message(message)) if message.proto_id == proto_id => {
+                return Some(message)
+            }
+            Ok(ChannelEvent::Service(ServiceEvent::SessionClose { .. })) => {
+                panic!("session closed")
+            }
+            _ => (),
+        }
+        None
+    }
+
+    /// Send the message until the remote receives it, a message sent on a stream
+    /// that is being replaced is lost
+    fn send_until_received(
+        control: &mut ServiceControl,
+        id: SessionId,
+        data: &[u8],
+        events: &Receiver<ChannelEvent>,
+        opened: &mut i32,
+    ) -> Message {
+        for _ in 0..50 {
+            let message = Message {
+                id,
+                proto_id: 2,
+                data: data.to_vec(),
+                ..Default::default()
+            };
+            control.send_message(Some(vec![id]), message).unwrap();
+            let deadline = Instant::now() + Duration::from_millis(200);
+            while Instant::now() < deadline {
+                let received = recv_protocol(events, 2, opened, Duration::from_millis(20));
+                if let Some(received) = received {
+                    return received;
+                }
+            }
+        }
+        panic!("message not received")
+    }
+
+    #[test]
+    fn test_simultaneous_open_protocol() {
+        let transport = MemoryTransport::new();
+        let create = || {
+            ServiceBuilder::default()
+                .insert_protocol(LazyProtocol(2))
+                .key_pair(SecioKeyPair::secp256k1_generated())
+                .transport(transport.clone())
+        };
+        let (server, client) = connect_channels(create(), create());
+        let (mut server_control, mut client_control) = (server.control, client.control);
+        let server_events = forward_events(server.events);
+        let client_events = forward_events(client.events);
+
+        let next_open =
+            |events: &Receiver<ChannelEvent>| match events.recv_timeout(Duration::from_secs(10)) {
+                Ok(ChannelEvent::Service(ServiceEvent::SessionOpen { id, .. })) => id,
+                event => panic!("unexpected event: {:?}", event),
+            };
+        let server_id = next_open(&server_events);
+        let client_id = next_open(&client_events);
+        // Both sides open the protocol at the same time
+        server_control.open_protocol(server_id, 2).unwrap();
+        client_control.open_protocol(client_id, 2).unwrap();
+
+        let (mut server_opened, mut client_opened) = (0, 0);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while server_opened == 0 || client_opened == 0 {
+            assert!(Instant::now() < deadline);
+            let timeout = Duration::from_millis(20);
+            assert!(recv_protocol(&server_events, 2, &mut server_opened, timeout).is_none());
+            assert!(recv_protocol(&client_events, 2, &mut client_opened, timeout).is_none());
+        }
+
+        // The stream kept by both sides carries the messages, the replaced one
+        // is reported as closed
+        let received = send_until_received(
+            &mut client_control,
+            client_id,
+            b"ping",
+            &server_events,
+            &mut server_opened,
+        );
+        assert_eq!(received.data, b"ping".to_vec());
+        assert_eq!(server_opened, 1);
+
+        let received = send_until_received(
+            &mut server_control,
+            server_id,
+            b"pong",
+            &client_events,
+            &mut client_opened,
+        );
+        assert_eq!(received.data, b"pong".to_vec());
+        assert_eq!(client_opened, 1);
+    }
+
+    #[test]
+    fn test_register_protocol() {
+        let transport = MemoryTransport::new();
+        let create = || {
+            ServiceBuilder::default()
+                .insert_protocol(LazyProtocol(1))
+                .key_pair(SecioKeyPair::secp256k1_generated())
+                .transport(transport.clone())
+        };
+        let (mut server, mut client) = connect_channels(create(), create());
+
+        let id = match client.events.next() {
+            Some(Ok(ChannelEvent::Service(ServiceEvent::SessionOpen { id, .. }))) => id,
+            event => panic!("unexpected event: {:?}", event),
+        };
+        assert_eq!(next_protocol_event(&mut client.events), (1, true));
+        assert_eq!(next_protocol_event(&mut server.events), (1, true));
+
+        assert_eq!(
+            client
+                .registry
+                .register(LazyProtocol(1))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        server.registry.register(LazyProtocol(2)).unwrap();
+        client.registry.register(LazyProtocol(2)).unwrap();
+        client.control.open_protocol(id, 2).unwrap();
+        assert_eq!(next_protocol_event(&mut client.events), (2, true));
+        assert_eq!(next_protocol_event(&mut server.events), (2, true));
+
+        // Closed on the open sessions, the other protocols are kept
+        client.registry.unregister(2).unwrap();
+        assert_eq!(next_protocol_event(&mut client.events), (2, false));
+        assert_eq!(next_protocol_event(&mut server.events), (2, false));
+        assert_eq!(
+            client.registry.unregister(2).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    /// Reports when its global handle is dropped
+    struct DropProtocol(Sender<()>);
+
+    impl ProtocolMeta for DropProtocol {
+        fn id(&self) -> ProtocolId {
+            2
+        }
+        fn codec(&self) -> Box<dyn Codec> {
+            Box::new(LengthDelimitedCodec::new())
+        }
+        fn open_on_connect(&self) -> bool {
+            false
+        }
+        fn handle(&self) -> Option<Box<dyn ProtocolHandle + Send + 'static>> {
+            Some(Box::new(DropHandle(Mutex::new(self.0.clone()))))
+        }
+    }
+
+    struct DropHandle(Mutex<Sender<()>>);
+
+    impl ProtocolHandle for DropHandle {}
+
+    impl Drop for DropHandle {
+        fn drop(&mut self) {
+            let _ = self.0.lock().unwrap().send(());
+        }
+    }
+
+    #[test]
+    fn test_unregister_closed_protocol() {
+        let transport = MemoryTransport::new();
+        let (sender, receiver) = channel();
+        let create = || {
+            ServiceBuilder::default()
+                .insert_protocol(LazyProtocol(1))
+                .key_pair(SecioKeyPair::secp256k1_generated())
+                .transport(transport.clone())
+        };
+        let (mut server, mut client) = connect_channels(
+            create().insert_protocol(LazyProtocol(2)),
+            create().insert_protocol(DropProtocol(sender)),
+        );
+
+        let id = match client.events.next() {
+            Some(Ok(ChannelEvent::Service(ServiceEvent::SessionOpen { id, .. }))) => id,
+            event => panic!("unexpected event: {:?}", event),
+        };
+        assert_eq!(next_protocol_event(&mut client.events), (1, true));
+        assert_eq!(next_protocol_event(&mut server.events), (1, true));
+
+        client.control.open_protocol(id, 2).unwrap();
+        assert_eq!(next_protocol_event(&mut client.events), (2, true));
+        client.control.close_protocol(id, 2).unwrap();
+        assert_eq!(next_protocol_event(&mut client.events), (2, false));
+        // Kept while the protocol is registered
+        assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+
+        // Dropped without a close event
+        client.registry.unregister(2).unwrap();
+        receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    }
+
+    struct InfoProtocol(Sender<SessionInfo>);
+
+    impl ProtocolMeta for InfoProtocol {
+        fn id(&self) -> ProtocolId {
+            1
+        }
+        fn codec(&self) -> Box<dyn Codec> {
+            Box::new(LengthDelimitedCodec::new())
+        }
+        fn handle(&self) -> Option<Box<dyn ProtocolHandle + Send + 'static>> {
+            Some(Box::new(InfoHandle(Mutex::new(self.0.clone()))))
+        }
+    }
+
+    struct InfoHandle(Mutex<Sender<SessionInfo>>);
+
+    impl ProtocolHandle for InfoHandle {
+        fn connected(
+            &mut self,
+            control: &mut ServiceContext,
+            session_id: SessionId,
+            _address: Multiaddr,
+            _ty: SessionType,
+            _remote_public_key: &Option<PublicKey>,
+            peer_id: Option<&PeerId>,
+            _version: &str,
+        ) {
+            assert_eq!(control.sessions().len(), 1);
+            let info = control.session(session_id).unwrap().clone();
+            assert_eq!(peer_id, info.peer_id.as_ref());
+            let _ = self.0.lock().unwrap().send(info);
+        }
+    }
+
+    #[test]
+    fn test_session_info() {
+        let transport = MemoryTransport::new();
+        let (sender, receiver) = channel();
+        let (event_sender, _event_receiver) = channel();
+        let server_key = SecioKeyPair::secp256k1_generated();
+        let create = |key_pair: SecioKeyPair| {
+            ServiceBuilder::default()
+                .insert_protocol(InfoProtocol(sender.clone()))
+                .key_pair(key_pair)
+                .transport(transport.clone())
+                .forever(true)
+                .build(SHandle {
+                    sender: event_sender.clone(),
+                    ban: false,
+                })
+        };
+
+        let mut server = create(server_key.clone());
+        let address = server.listen("/memory/server".parse().unwrap()).unwrap();
+        run(server);
+        run(create(SecioKeyPair::secp256k1_generated()).dial(address.clone()));
+
+        let mut infos = (0..2)
+            .map(|_| receiver.recv_timeout(Duration::from_secs(10)).unwrap())
+            .collect::<Vec<_>>();
+        infos.sort_by_key(|info| info.ty == SessionType::Server);
+        let (client, server) = (&infos[0], &infos[1]);
+        assert_eq!(client.ty, SessionType::Client);
+        assert_eq!(client.address, address);
+        assert_eq!(client.public_key, Some(server_key.to_public_key()));
+        assert_eq!(server.ty, SessionType::Server);
+        for info in &infos {
+            assert_eq!(info.protocols.get(&1).map(String::as_str), Some("1.0.0"));
+            assert!(info.connected_at.elapsed() < Duration::from_secs(10));
+        }
+    }
+
+    struct RawProtocol;
+
+    impl ProtocolMeta for RawProtocol {
+        fn id(&self) -> ProtocolId {
+            2
+        }
+        fn codec(&self) -> Box<dyn Codec> {
+            Box::new(BytesCodec::new())
+        }
+    }
+
+    #[test]
+    fn test_mixed_codecs() {
+        let transport = MemoryTransport::new();
+        let create = || builder(&transport).insert_protocol(RawProtocol);
+        let (mut server, mut client) = connect_channels(create(), create());
+
+        let mut opened = client
+            .events
+            .by_ref()
+            .filter_map(|event| match event {
+                Ok(ChannelEvent::ProtocolOpen { id, proto_id, .. }) => Some((id, proto_id)),
+                _ => None,
+            })
+            .take(2)
+            .collect::<Vec<_>>();
+        opened.sort();
+        let id = opened[0].0;
+        assert_eq!(opened, vec![(id, 1), (id, 2)]);
+
+        for proto_id in [2, 1].iter() {
+            let message = Message {
+                id,
+                proto_id: *proto_id,
+                data: b"hello".to_vec(),
+                ..Default::default()
+            };
+            client
+                .control
+                .send_message(Some(vec![id]), message)
+                .unwrap();
+        }
+        let mut received = server
+            .events
+            .by_ref()
+            .filter_map(|event| match event {
+                Ok(ChannelEvent::Message(message)) => Some((message.proto_id, message.data)),
+                _ => None,
+            })
+            .take(2)
+            .collect::<Vec<_>>();
+        received.sort();
+        assert_eq!(
+            received,
+            vec![(1, b"hello".to_vec()), (2, b"hello".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_session_config() {
+        let transport = MemoryTransport::new();
+        let create = |cipher| {
+            ServiceBuilder::default()
+                .insert_protocol(TestProtocol)
+                .key_pair(SecioKeyPair::secp256k1_generated())
+                .transport(transport.clone())
+                .ciphers(vec![cipher])
+                .yamux_config(YamuxConfig {
+                    max_stream_window_size: 1024 * 1024,
+                    ..Default::default()
+                })
+                .handshake_timeout(Duration::from_secs(5))
+                .protocol_timeout(Duration::from_secs(5))
+                .forever(true)
+                .build_channel()
+        };
+        let (mut server, _server_events) = create(Cipher::Aes256);
+        let address = server.listen("/memory/server".parse().unwrap()).unwrap();
+        thread::spawn(|| tokio::run(server.for_each(|_| Ok(()))));
+
+        // No common cipher
+        let (client, client_events) = create(Cipher::Aes128);
+        let client = client.dial(address.clone());
+        thread::spawn(|| tokio::run(client.for_each(|_| Ok(()))));
+        let failed = client_events.wait().any(|event| match event {
+            Ok(ChannelEvent::Error(ServiceEvent::HandshakeError { ty, .. })) => {
+                assert_eq!(ty, SessionType::Client);
+                true
+            }
+            Ok(ChannelEvent::ProtocolOpen { .. }) => panic!("unexpected protocol open"),
+            _ => false,
+        });
+        assert!(failed);
+
+        let (client, client_events) = create(Cipher::Aes256);
+        let client = client.dial(address);
+        thread::spawn(|| tokio::run(client.for_each(|_| Ok(()))));
+        let opened = client_events
+            .wait()
+            .any(|event| matches!(event, Ok(ChannelEvent::ProtocolOpen { .. })));
+        assert!(opened);
+    }
+
+    #[test]
+    fn test_peer_id() {
+        let transport = MemoryTransport::new();
+        let server_key = SecioKeyPair::secp256k1_generated();
+        let server_peer_id = server_key.peer_id();
+        let (mut server, mut client) = connect_channels(
+            builder_with_key(&transport, server_key),
+            builder(&transport),
+        );
+
+        match client.events.next() {
+            Some(Ok(ChannelEvent::Service(ServiceEvent::SessionOpen {
+                peer_id: Some(peer_id),
+                ..
+            }))) => assert_eq!(peer_id, server_peer_id),
+            event => panic!("unexpected event: {:?}", event),
+        }
+        let opened = client.events.by_ref().find_map(|event| match event {
+            Ok(ChannelEvent::ProtocolOpen { peer_id, .. }) => Some(peer_id),
+            _ => None,
+        });
+        assert_eq!(opened, Some(Some(server_peer_id.clone())));
+
+        let message = Message {
+            proto_id: 1,
+            data: b"hello".to_vec(),
+            ..Default::default()
+        };
+        client
+            .control
+            .send_message_to_peer(server_peer_id.clone(), message)
+            .unwrap();
+        let received = server.events.by_ref().find_map(|event| match event {
+            Ok(ChannelEvent::Message(message)) => Some(message.data),
+            _ => None,
+        });
+        assert_eq!(received, Some(b"hello".to_vec()));
+
+        client
+            .control
+            .disconnect_peer(server_peer_id.clone())
+            .unwrap();
+        let closed = client.events.by_ref().any(|event| {
+            matches!(
+                event,
+                Ok(ChannelEvent::Service(ServiceEvent::SessionClose { .. }))
+            )
+        });
+        assert!(closed);
+
+        // Reported with the peer id
+        let message = Message {
+            id: 10,
+            proto_id: 1,
+            data: b"hello".to_vec(),
+            ..Default::default()
+        };
+        client
+            .control
+            .send_message_to_peer(server_peer_id.clone(), message)
+            .unwrap();
+        let dropped = client.events.find_map(|event| match event {
+            Ok(ChannelEvent::Error(ServiceEvent::MessageDropped {
+                session_id,
+                peer_id,
+                reason,
+                ..
+            })) => Some((session_id, peer_id, reason)),
+            _ => None,
+        });
+        assert_eq!(
+            dropped,
+            Some((None, Some(server_peer_id), DropReason::PeerNotConnected))
+        );
+    }
+
+    #[test]
+    fn test_simultaneous_dial() {
+        let transport = MemoryTransport::new();
+        let keys = [
+            SecioKeyPair::secp256k1_generated(),
+            SecioKeyPair::secp256k1_generated(),
+        ];
+        let mut services = Vec::new();
+        let mut receivers = Vec::new();
+        let mut addresses = Vec::new();
+        for (i, key_pair) in keys.iter().enumerate() {
+            let (sender, receiver) = channel();
+            let mut service = builder_with_key(&transport, key_pair.clone())
+                .forever(true)
+                .build(SHandle { sender, ban: false });
+            let address = format!("/memory/{}", i).parse().unwrap();
+            addresses.push(service.listen(address).unwrap());
+            services.push(service);
+            receivers.push(receiver);
+        }
+        // Dial each other at the same time
+        for (i, service) in services.into_iter().enumerate() {
+            run(service.dial(addresses[1 - i].clone()));
+        }
+
+        // The connection dialed by the lower peer id is kept
+        let first_is_lower = keys[0].peer_id() < keys[1].peer_id();
+        let mut duplicates = 0;
+        for (i, receiver) in receivers.iter().enumerate() {
+            let mut sessions = HashSet::new();
+            while let Ok(event) = receiver.recv_timeout(Duration::from_secs(2)) {
+                match event {
+                    ServiceEvent::SessionOpen { id, .. } => {
+                        sessions.insert(id);
+                    }
+                    ServiceEvent::SessionClose { id, .. } => {
+                        sessions.remove(&id);
+                    }
+                    ServiceEvent::DuplicateConnection {
+                        peer_id,
+                        kept,
+                        kept_ty,
+                        closed_ty,
+                        ..
+                    } => {
+                        assert_eq!(peer_id, keys[1 - i].peer_id());
+                        assert!(sessions.contains(&kept));
+                        assert_ne!(kept_ty, closed_ty);
+                        let local_is_lower = (i == 0) == first_is_lower;
+                        assert_eq!(kept_ty == SessionType::Client, local_is_lower);
+                        duplicates += 1;
+                    }
+                    _ => (),
+                }
+            }
+            // Both sides keep the same connection, or it would be closed by the other side
+            assert_eq!(sessions.len(), 1);
+        }
+        assert!(duplicates > 0);
+    }
+
+    #[test]
+    fn test_duplicate_dial() {
+        let transport = MemoryTransport::new();
+        let mut receivers = Vec::new();
+        let mut server = {
+            let (sender, receiver) = channel();
+            receivers.push(receiver);
+            builder_with_key(&transport, SecioKeyPair::secp256k1_generated())
+                .forever(true)
+                .build(SHandle { sender, ban: false })
+        };
+        let options = |tag| DialOptions {
+            tag: Some(tag),
+            ..Default::default()
+        };
+        // Two connections with the same peer dialed by the same side, through two
+        // listen addresses of the server
+        let first = server.listen("/memory/first".parse().unwrap()).unwrap();
+        let second = server.listen("/memory/second".parse().unwrap()).unwrap();
+        let client = {
+            let (sender, receiver) = channel();
+            receivers.push(receiver);
+            builder_with_key(&transport, SecioKeyPair::secp256k1_generated())
+                .forever(true)
+                .build(SHandle { sender, ban: false })
+                .dial_with(first, options(1))
+                .dial_with(second, options(2))
+        };
+        run(server);
+        run(client);
+
+        let mut duplicates = 0;
+        let mut tags = Vec::new();
+        for receiver in receivers.iter() {
+            let mut sessions = HashSet::new();
+            while let Ok(event) = receiver.recv_timeout(Duration::from_secs(2)) {
+                match event {
+                    ServiceEvent::SessionOpen { id, tag, .. } => {
+                        sessions.insert(id);
+                        tags.extend(tag);
+                    }
+                    ServiceEvent::SessionClose { id, .. } => {
+                        sessions.remove(&id);
+                    }
+                    ServiceEvent::DuplicateConnection {
+                        kept,
+                        kept_ty,
+                        closed_ty,
+                        closed_tag,
+                        ..
+                    } => {
+                        assert!(sessions.contains(&kept));
+                        assert_eq!(kept_ty, closed_ty);
+                        tags.extend(closed_tag);
+                        duplicates += 1;
+                    }
+                    _ => (),
+                }
+            }
+            // Both sides keep the same connection, or it would be closed by the other side
+            assert_eq!(sessions.len(), 1);
+        }
+        assert_eq!(duplicates, 2);
+        // Each dial is resolved by one event
+        tags.sort();
+        assert_eq!(tags, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_keep_duplicate_connection() {
+        let service =
+            builder_with_key(&MemoryTransport::new(), SecioKeyPair::secp256k1_generated()).build(
+                SHandle {
+                    sender: channel().0,
+                    ban: false,
+                },
+            );
+        let remote = SecioKeyPair::secp256k1_generated().to_public_key();
+        let nonces = [vec![1; 32], vec![2; 32]];
+        // Whichever connection is opened first, the one with the higher nonces is kept
+        for (existing, new) in [(0, 1), (1, 0)].iter() {
+            let existing = SessionController {
+                sender: mpsc::channel(1).0,
+                address: "/memory/0".parse().unwrap(),
+                ty: SessionType::Client,
+                nonces: Some(nonces[*existing].clone()),
+            };
+            let keep = service.keep_new_connection(
+                &remote,
+                &existing,
+                SessionType::Client,
+                Some(&nonces[*new]),
+            );
+            assert_eq!(keep, *new == 1);
+        }
+    }
+
+    #[test]
+    fn test_traffic_stats() {
+        let transport = MemoryTransport::new();
+        let (sender, receiver) = channel();
+        let create = |sender: Sender<SessionInfo>| {
+            ServiceBuilder::default()
+                .insert_protocol(InfoProtocol(sender))
+                .key_pair(SecioKeyPair::secp256k1_generated())
+                .transport(transport.clone())
+        };
+        let (mut server, mut client) = connect_channels(create(sender), create(channel().0));
+
+        let id = client
+            .events
+            .by_ref()
+            .find_map(|event| match event {
+                Ok(ChannelEvent::ProtocolOpen { id, .. }) => Some(id),
+                _ => None,
+            })
+            .unwrap();
+        for _ in 0..5 {
+            let message = Message {
+                id,
+                proto_id: 1,
+                data: b"hello".to_vec(),
+                ..Default::default()
+            };
+            client
+                .control
+                .send_message(Some(vec![id]), message)
+                .unwrap();
+        }
+        let received = server
+            .events
+            .by_ref()
+            .filter(|event| matches!(event, Ok(ChannelEvent::Message(_))))
+            .take(5)
+            .count();
+        assert_eq!(received, 5);
+
+        let info = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(info.traffic.frames_in(), 5);
+        assert_eq!(info.traffic.bytes_in(), 25);
+        assert_eq!(info.traffic.dropped(), 0);
+        let protocol = &info.protocol_traffic[&1];
+        assert_eq!(protocol.frames_in(), 5);
+        assert_eq!(protocol.bytes_in(), 25);
+        let secure = info.secure_traffic.as_ref().unwrap();
+        assert!(secure.frames_in() >= 5);
+        assert!(secure.bytes_in() > 25);
+        assert!(info.traffic.idle() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_message_dropped() {
+        let transport = MemoryTransport::new();
+        let (_server, mut client) = connect_channels(builder(&transport), builder(&transport));
+
+        let id = client
+            .events
+            .find_map(|event| match event {
+                Ok(ChannelEvent::ProtocolOpen { id, .. }) => Some(id),
+                _ => None,
+            })
+            .unwrap();
+        let mut next_dropped = |session_id, proto_id| {
+            let message = Message {
+                id: session_id,
+                proto_id,
+                data: b"hello".to_vec(),
+                ..Default::default()
+            };
+            client
+                .control
+                .send_message(Some(vec![session_id]), message)
+                .unwrap();
+            client
+                .events
+                .find_map(|event| match event {
+                    Ok(ChannelEvent::Error(ServiceEvent::MessageDropped {
+                        session_id,
+                        proto_id,
+                        reason,
+                        ..
+                    })) => Some((session_id.unwrap(), proto_id, reason)),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        // Dropped by the session
+        assert_eq!(next_dropped(id, 2), (id, 2, DropReason::ProtocolNotOpen));
+        // Dropped by the service
+        assert_eq!(
+            next_dropped(id + 1, 1),
+            (id + 1, 1, DropReason::SessionClosed)
+        );
+    }
+
+    /// The client sends more messages than the channels can hold
+    struct FloodProtocol;
+
+    impl ProtocolMeta for FloodProtocol {
+        fn id(&self) -> ProtocolId {
+            1
+        }
+        fn codec(&self) -> Box<dyn Codec> {
+            Box::new(LengthDelimitedCodec::new())
+        }
+        fn handle(&self) -> Option<Box<dyn ProtocolHandle + Send + 'static>> {
+            Some(Box::new(FloodHandle))
+        }
+    }
+
+    struct FloodHandle;
+
+    impl ProtocolHandle for FloodHandle {
+        fn connected(
+            &mut self,
+            control: &mut ServiceContext,
+            session_id: SessionId,
+            _address: Multiaddr,
+            ty: SessionType,
+            _remote_public_key: &Option<PublicKey>,
+            _peer_id: Option<&PeerId>,
+            _version: &str,
+        ) {
+            if ty != SessionType::Client {
+                return;
+            }
+            let message = || Message {
+                id: session_id,
+                proto_id: 1,
+                data: b"hello".to_vec(),
+                ..Default::default()
+            };
+            // Fills the session channel
+            control.send_message(Some(vec![session_id; 300]), message());
+            // Fills the service channel
+            for _ in 0..300 {
+                control.send_message(Some(vec![session_id]), message());
+            }
+        }
+    }
+
+    #[test]
+    fn test_channels_full() {
+        let transport = MemoryTransport::new();
+        let (mut server, _server_events) = ServiceBuilder::default()
+            .insert_protocol(TestProtocol)
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .transport(transport.clone())
+            .forever(true)
+            .build_channel();
+        let address = server.listen("/memory/server".parse().unwrap()).unwrap();
+        let (client, client_events) = ServiceBuilder::default()
+            .insert_protocol(FloodProtocol)
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .transport(transport.clone())
+            .forever(true)
+            .build_channel();
+        let metrics = client.metrics();
+        let client = client.dial(address);
+        thread::spawn(|| tokio::run(server.for_each(|_| Ok(()))));
+        // The channels are filled before their receivers are polled
+        thread::spawn(|| {
+            tokio::runtime::current_thread::run(client.for_each(|_| Ok(())).map_err(|_| ()))
+        });
+
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            for event in client_events.wait() {
+                if let Ok(ChannelEvent::Error(ServiceEvent::MessageDropped { reason, .. })) = event
+                {
+                    let _ = sender.send(reason);
+                }
+            }
+        });
+        let (mut service_busy, mut session_busy, mut protocol_busy) = (0, 0, 0);
+        while let Ok(reason) = receiver.recv_timeout(Duration::from_secs(2)) {
+            match reason {
+                DropReason::ServiceBusy => service_busy += 1,
+                DropReason::SessionBusy => session_busy += 1,
+                DropReason::ProtocolBusy => protocol_busy += 1,
+                reason => panic!("unexpected reason: {:?}", reason),
+            }
+        }
+        assert!(service_busy > 0);
+        assert!(session_busy > 0);
+        assert!(protocol_busy > 0);
+        // Every message is either queued to the protocol stream or reported
+        let traffic = metrics.traffic();
+        assert_eq!(traffic.frames_out() + traffic.dropped(), 600);
+        assert_eq!(
+            service_busy + session_busy + protocol_busy,
+            traffic.dropped()
+        );
+    }
+
+    #[test]
+    fn test_session_lifecycle_events() {
+        let transport = MemoryTransport::new();
+        let (server_sender, server_receiver) = channel();
+        let (client_sender, client_receiver) = channel();
+
+        let mut server = builder(&transport)
+            .handshake_timeout(Duration::from_secs(1))
+            .forever(true)
+            .build(SHandle {
+                sender: server_sender,
+                ban: false,
+            });
+        let address = server.listen("/memory/server".parse().unwrap()).unwrap();
+        run(server);
+        let client = builder(&transport).forever(true).build(SHandle {
+            sender: client_sender,
+            ban: false,
+        });
+        let mut control = client.control();
+        run(client.dial(address.clone()));
+
+        let next_open = |receiver: &Receiver<ServiceEvent>| match receiver
+            .recv_timeout(Duration::from_secs(10))
+            .unwrap()
+        {
+            ServiceEvent::SessionOpen {
+                id, ty, negotiated, ..
+            } => (id, ty, negotiated.unwrap()),
+            event => panic!("unexpected event {:?}", event),
+        };
+        let (id, ty, client_negotiated) = next_open(&client_receiver);
+        assert_eq!(ty, SessionType::Client);
+        let (_, ty, server_negotiated) = next_open(&server_receiver);
+        assert_eq!(ty, SessionType::Server);
+        assert_eq!(client_negotiated, server_negotiated);
+
+        // The remote receives the GoAway sent on disconnect
+        control.disconnect(id).unwrap();
+        match client_receiver
+            .recv_timeout(Duration::from_secs(10))
+            .unwrap()
+        {
+            ServiceEvent::SessionClose {
+                id: closed,
+                reason: CloseReason::LocalDisconnect,
+            } => assert_eq!(closed, id),
+            event => panic!("unexpected event {:?}", event),
+        }
+        match server_receiver
+            .recv_timeout(Duration::from_secs(10))
+            .unwrap()
+        {
+            ServiceEvent::SessionClose {
+                reason: CloseReason::GoAway,
+                ..
+            } => (),
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        // The inbound handshake with a client without secio fails
+        let plain = ServiceBuilder::default()
+            .insert_protocol(TestProtocol)
+            .transport(transport.clone())
+            .build(SHandle {
+                sender: channel().0,
+                ban: false,
+            });
+        run(plain.dial(address));
+        match server_receiver
+            .recv_timeout(Duration::from_secs(10))
+            .unwrap()
+        {
+            ServiceEvent::HandshakeError { ty, tag, .. } => {
+                assert_eq!(ty, SessionType::Server);
+                assert_eq!(tag, None);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+}
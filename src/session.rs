@@ -1,15 +1,24 @@
 use futures::{prelude::*, sync::mpsc};
 use log::{debug, error, trace, warn};
-use secio::{codec::stream_handle::StreamHandle as SecureHandle, PublicKey};
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::{error, io, net::SocketAddr, time::Duration};
-use tokio::codec::{Decoder, Encoder, Framed};
+use secio::{
+    codec::{stats::TrafficStats, stream_handle::StreamHandle as SecureHandle},
+    PublicKey,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::{io, time::Duration};
+use tokio::codec::Framed;
 use tokio::prelude::{AsyncRead, AsyncWrite, FutureExt};
-use yamux::{session::SessionType, Config, Session as YamuxSession, StreamHandle};
-
+use yamux::{
+    session::SessionType, Config, Error as YamuxError, Priority, PriorityQueue,
+    Session as YamuxSession, StreamHandle,
+};
+
+use crate::codec::Codec;
+use crate::metrics::Metrics;
+use crate::multiaddr::Multiaddr;
 use crate::protocol_select::{client_select, server_select, ProtocolInfo};
-use crate::service::ProtocolHandle;
+use crate::service::{CloseReason, DialOptions, DropReason, ProtocolHandle};
 use crate::substream::{ProtocolEvent, SubStream};
 
 /// Index of sub/protocol stream
@@ -18,6 +27,8 @@ pub type StreamId = usize;
 pub type ProtocolId = usize;
 /// Index of session
 pub type SessionId = usize;
+/// Protocols by name, shared by the service and its sessions, changed by `ProtocolRegistry`
+pub type ProtocolConfigs = Arc<RwLock<HashMap<String, Box<dyn ProtocolMeta + Send + Sync>>>>;
 
 /// Event generated/received by the Session
 #[derive(Debug)]
@@ -26,6 +37,20 @@ pub(crate) enum SessionEvent {
     SessionClose {
         /// Session id
         id: SessionId,
+        /// Why the session is closed
+        reason: CloseReason,
+    },
+    /// Close the session after the protocol streams are flushed
+    Shutdown,
+    /// Open a protocol stream if it's not open
+    OpenProtocol {
+        /// Protocol id
+        proto_id: ProtocolId,
+    },
+    /// Close a protocol stream after the queued messages are flushed
+    CloseProtocol {
+        /// Protocol id
+        proto_id: ProtocolId,
     },
     HandshakeSuccess {
         /// Secure handle
@@ -33,15 +58,21 @@ pub(crate) enum SessionEvent {
         /// Remote Public key
         public_key: PublicKey,
         /// Remote address
-        address: SocketAddr,
+        address: Multiaddr,
         /// Session type
         ty: SessionType,
+        /// Dial options, None if inbound
+        dial: Option<DialOptions>,
     },
     HandshakeFail {
+        /// Remote address
+        address: Multiaddr,
         /// Session type
         ty: SessionType,
         /// If fail
         error: io::Error,
+        /// Dial options, None if inbound
+        dial: Option<DialOptions>,
     },
     /// Protocol data
     ProtocolMessage {
@@ -51,6 +82,8 @@ pub(crate) enum SessionEvent {
         proto_id: ProtocolId,
         /// Data
         data: bytes::Bytes,
+        /// Priority of sending
+        priority: Priority,
     },
     /// Protocol open event
     ProtocolOpen {
@@ -61,13 +94,15 @@ pub(crate) enum SessionEvent {
         /// Stream id
         stream_id: StreamId,
         /// Remote address
-        remote_address: ::std::net::SocketAddr,
+        remote_address: Multiaddr,
         /// Remote public key
         remote_public_key: Option<PublicKey>,
         /// Session type
         ty: SessionType,
         /// Protocol version
         version: String,
+        /// Traffic of the protocol stream
+        traffic: Arc<TrafficStats>,
     },
     /// Protocol close event
     ProtocolClose {
@@ -78,15 +113,19 @@ pub(crate) enum SessionEvent {
         /// Stream id
         stream_id: StreamId,
     },
+    /// A message from service can't be sent to the protocol stream
+    MessageDropped {
+        /// Session id
+        id: SessionId,
+        /// Protocol id
+        proto_id: ProtocolId,
+        /// The reason
+        reason: DropReason,
+    },
 }
 
 /// Define the minimum data required for a custom protocol
-pub trait ProtocolMeta<U>
-where
-    U: Decoder<Item = bytes::BytesMut> + Encoder<Item = bytes::Bytes> + Send + 'static,
-    <U as Decoder>::Error: error::Error + Into<io::Error>,
-    <U as Encoder>::Error: error::Error + Into<io::Error>,
-{
+pub trait ProtocolMeta {
     /// Protocol name, default is "/p2p/protocol_id"
     #[inline]
     fn name(&self) -> String {
@@ -98,8 +137,17 @@ where
     fn support_versions(&self) -> Vec<String> {
         vec!["1.0.0".to_owned()]
     }
-    /// The codec used by the custom protocol, such as `LengthDelimitedCodec` by tokio
-    fn codec(&self) -> U;
+    /// The codec used by the custom protocol, such as `LengthDelimitedCodec` by tokio.
+    ///
+    /// Each protocol of a service can use its own codec
+    fn codec(&self) -> Box<dyn Codec>;
+    /// Whether the client opens the protocol when the session is established, default is true.
+    ///
+    /// Otherwise, open it by `ServiceContext::open_protocol`
+    #[inline]
+    fn open_on_connect(&self) -> bool {
+        true
+    }
     /// A global callback handle for a protocol.
     ///
     /// ---
@@ -129,14 +177,14 @@ where
 }
 
 /// Wrapper for real data streams, such as TCP stream
-pub(crate) struct Session<T, U> {
+pub(crate) struct Session<T> {
     socket: YamuxSession<T>,
 
-    protocol_configs: Arc<HashMap<String, Box<dyn ProtocolMeta<U> + Send + Sync>>>,
+    protocol_configs: ProtocolConfigs,
 
     id: SessionId,
 
-    remote_address: ::std::net::SocketAddr,
+    remote_address: Multiaddr,
     remote_public_key: Option<PublicKey>,
 
     next_stream: StreamId,
@@ -146,6 +194,8 @@ pub(crate) struct Session<T, U> {
     /// Sub streams maps a stream id to a sender of sub stream
     sub_streams: HashMap<StreamId, mpsc::Sender<ProtocolEvent>>,
     proto_streams: HashMap<ProtocolId, StreamId>,
+    /// The protocol streams opened by the yamux client side
+    client_streams: HashSet<StreamId>,
 
     /// Clone to new sub stream
     proto_event_sender: mpsc::Sender<ProtocolEvent>,
@@ -156,21 +206,34 @@ pub(crate) struct Session<T, U> {
     service_sender: mpsc::Sender<SessionEvent>,
     /// Receive event from service
     service_receiver: mpsc::Receiver<SessionEvent>,
+    /// Dropped messages waiting for room in the service channel to be reported
+    dropped_messages: VecDeque<(ProtocolId, DropReason)>,
+
+    /// Wait for the protocol streams to close before closing the session
+    closing: bool,
+    /// The connection is closed by remote, the received data can still be read by sub streams
+    remote_closed: bool,
+    /// The first cause of the closing, reported to the service on close
+    close_reason: Option<CloseReason>,
+
+    /// Timeout of a protocol negotiation
+    protocol_timeout: Duration,
+
+    /// Protocol messages of all the sub streams
+    traffic: Arc<TrafficStats>,
+    metrics: Arc<Metrics>,
 }
 
-impl<T, U> Session<T, U>
+impl<T> Session<T>
 where
     T: AsyncRead + AsyncWrite,
-    U: Decoder<Item = bytes::BytesMut> + Encoder<Item = bytes::Bytes> + Send + 'static,
-    <U as Decoder>::Error: error::Error + Into<io::Error>,
-    <U as Encoder>::Error: error::Error + Into<io::Error>,
 {
     /// New a session
     pub fn new(
         socket: T,
         service_sender: mpsc::Sender<SessionEvent>,
         service_receiver: mpsc::Receiver<SessionEvent>,
-        meta: SessionMeta<U>,
+        meta: SessionMeta,
     ) -> Self {
         let socket = YamuxSession::new(socket, meta.config, meta.ty);
         let (proto_event_sender, proto_event_receiver) = mpsc::channel(256);
@@ -184,10 +247,18 @@ where
             next_stream: 0,
             sub_streams: HashMap::default(),
             proto_streams: HashMap::default(),
+            client_streams: HashSet::default(),
             proto_event_sender,
             proto_event_receiver,
             service_sender,
             service_receiver,
+            dropped_messages: VecDeque::new(),
+            closing: false,
+            remote_closed: false,
+            close_reason: None,
+            protocol_timeout: meta.protocol_timeout,
+            traffic: meta.traffic,
+            metrics: meta.metrics,
         }
     }
 
@@ -195,12 +266,18 @@ where
     pub fn open_proto_stream(&mut self, proto_name: &str) {
         debug!("try open proto, {}", proto_name);
         let event_sender = self.proto_event_sender.clone();
-        let handle = self.socket.open_stream().unwrap();
-        let versions = self
-            .protocol_configs
-            .get(proto_name)
-            .unwrap()
-            .support_versions();
+        let versions = match self.protocol_configs.read().unwrap().get(proto_name) {
+            Some(proto) => proto.support_versions(),
+            // Unregistered
+            None => return,
+        };
+        let handle = match self.socket.open_stream() {
+            Ok(handle) => handle,
+            Err(err) => {
+                debug!("open proto [{}] failed: {:?}", proto_name, err);
+                return;
+            }
+        };
         let proto_info = ProtocolInfo::new(&proto_name, versions);
 
         let task = client_select(handle, proto_info)
@@ -224,7 +301,7 @@ where
                 }
                 Ok(())
             })
-            .timeout(Duration::from_secs(10))
+            .timeout(self.protocol_timeout)
             .map_err(|err| {
                 trace!("stream protocol select err: {:?}", err);
             });
@@ -236,6 +313,32 @@ where
     fn event_output(&mut self, event: SessionEvent) {
         if let Err(e) = self.service_sender.try_send(event) {
             error!("session send to service error: {}", e);
+            if e.is_full() {
+                self.metrics.channel_overflow();
+            }
+        }
+    }
+
+    /// Report the dropped messages while the service channel has room,
+    /// the session is notified when the channel is available again
+    fn report_dropped(&mut self) {
+        while !self.dropped_messages.is_empty() {
+            match self.service_sender.poll_ready() {
+                Ok(Async::Ready(())) => {
+                    let (proto_id, reason) = self.dropped_messages.pop_front().unwrap();
+                    self.event_output(SessionEvent::MessageDropped {
+                        id: self.id,
+                        proto_id,
+                        reason,
+                    });
+                }
+                Ok(Async::NotReady) => break,
+                Err(_) => {
+                    // The service is stopped
+                    self.dropped_messages.clear();
+                    break;
+                }
+            }
         }
     }
 
@@ -244,6 +347,8 @@ where
         let event_sender = self.proto_event_sender.clone();
         let proto_metas = self
             .protocol_configs
+            .read()
+            .unwrap()
             .values()
             .map(|proto_meta| {
                 let name = proto_meta.name();
@@ -277,7 +382,7 @@ where
                 }
                 Ok(())
             })
-            .timeout(Duration::from_secs(10))
+            .timeout(self.protocol_timeout)
             .map_err(|err| {
                 trace!("stream protocol select err: {:?}", err);
             });
@@ -285,6 +390,26 @@ where
         tokio::spawn(task);
     }
 
+    /// Close the protocol stream replaced by the one opened by the yamux client
+    fn replace_proto_stream(&mut self, stream_id: StreamId, proto_id: ProtocolId) {
+        debug!(
+            "session [{}] proto [{}] replace stream [{}]",
+            self.id, proto_id, stream_id
+        );
+        self.proto_streams.remove(&proto_id);
+        if let Some(sender) = self.sub_streams.get_mut(&stream_id) {
+            let _ = sender.try_send(ProtocolEvent::ProtocolClose {
+                id: stream_id,
+                proto_id,
+            });
+        }
+        self.event_output(SessionEvent::ProtocolClose {
+            id: self.id,
+            proto_id,
+            stream_id,
+        });
+    }
+
     /// Handling events uploaded by the protocol stream
     fn handle_stream_event(&mut self, event: ProtocolEvent) {
         match event {
@@ -293,33 +418,63 @@ where
                 sub_stream,
                 version,
             } => {
-                let proto = match self.protocol_configs.get(&proto_name) {
-                    Some(proto) => proto,
-                    None => unreachable!(),
+                let proto = self
+                    .protocol_configs
+                    .read()
+                    .unwrap()
+                    .get(&proto_name)
+                    .map(|proto| (proto.id(), proto.codec()));
+
+                // The protocol may be unregistered during the negotiation
+                let (proto_id, codec) = match proto {
+                    Some((proto_id, codec)) if !self.closing => (proto_id, codec),
+                    _ => {
+                        let mut sub_stream = sub_stream;
+                        let _ = sub_stream.shutdown();
+                        return;
+                    }
                 };
+                // Yamux client opens the odd streams
+                let client_opened = sub_stream.id() % 2 == 1;
+                // Only one stream for a protocol, if both sides open it at the same time,
+                // both keep the one opened by the yamux client
+                if let Some(&stream_id) = self.proto_streams.get(&proto_id) {
+                    if !client_opened || self.client_streams.contains(&stream_id) {
+                        let mut sub_stream = sub_stream;
+                        let _ = sub_stream.shutdown();
+                        return;
+                    }
+                    self.replace_proto_stream(stream_id, proto_id);
+                }
 
-                let proto_id = proto.id();
-                let frame = Framed::new(sub_stream, proto.codec());
+                let frame = Framed::new(sub_stream, codec);
                 let (session_to_proto_sender, session_to_proto_receiver) = mpsc::channel(32);
+                let traffic = Arc::new(TrafficStats::new());
                 let proto_stream = SubStream::new(
                     frame,
                     self.proto_event_sender.clone(),
                     session_to_proto_receiver,
                     self.next_stream,
                     proto_id,
+                    traffic.clone(),
+                    self.metrics.clone(),
                 );
                 self.sub_streams
                     .insert(self.next_stream, session_to_proto_sender);
                 self.proto_streams.insert(proto_id, self.next_stream);
+                if client_opened {
+                    self.client_streams.insert(self.next_stream);
+                }
 
                 self.event_output(SessionEvent::ProtocolOpen {
                     id: self.id,
                     stream_id: self.next_stream,
                     proto_id,
-                    remote_address: self.remote_address,
+                    remote_address: self.remote_address.clone(),
                     remote_public_key: self.remote_public_key.clone(),
                     ty: self.ty,
                     version,
+                    traffic,
                 });
                 self.next_stream += 1;
 
@@ -330,6 +485,11 @@ where
             ProtocolEvent::ProtocolClose { id, proto_id } => {
                 debug!("session [{}] proto [{}] closed", self.id, proto_id);
                 let _ = self.sub_streams.remove(&id);
+                self.client_streams.remove(&id);
+                // The replaced stream is already reported
+                if self.proto_streams.get(&proto_id) != Some(&id) {
+                    return;
+                }
                 let _ = self.proto_streams.remove(&proto_id);
                 self.event_output(SessionEvent::ProtocolClose {
                     id: self.id,
@@ -339,10 +499,13 @@ where
             }
             ProtocolEvent::ProtocolMessage { data, proto_id, .. } => {
                 debug!("get proto [{}] data: {:?}", proto_id, data);
+                self.traffic.record_in(data.len());
+                self.metrics.traffic().record_in(data.len());
                 self.event_output(SessionEvent::ProtocolMessage {
                     id: self.id,
                     proto_id,
                     data,
+                    priority: Priority::Normal,
                 })
             }
         }
@@ -351,32 +514,122 @@ where
     /// Handling events send by the service
     fn handle_session_event(&mut self, event: SessionEvent) {
         match event {
-            SessionEvent::ProtocolMessage { proto_id, data, .. } => {
-                if let Some(stream_id) = self.proto_streams.get(&proto_id) {
-                    if let Some(sender) = self.sub_streams.get_mut(stream_id) {
-                        let _ = sender.try_send(ProtocolEvent::ProtocolMessage {
-                            id: *stream_id,
+            SessionEvent::ProtocolMessage {
+                proto_id,
+                data,
+                priority,
+                ..
+            } => {
+                let len = data.len();
+                let sub_streams = &mut self.sub_streams;
+                let sender = self
+                    .proto_streams
+                    .get(&proto_id)
+                    .and_then(|stream_id| Some((*stream_id, sub_streams.get_mut(stream_id)?)));
+                let result = match sender {
+                    Some((stream_id, sender)) => sender
+                        .try_send(ProtocolEvent::ProtocolMessage {
+                            id: stream_id,
                             proto_id,
                             data,
-                        });
-                    };
-                } else {
-                    trace!("protocol {} not ready", proto_id);
+                            priority,
+                        })
+                        .map_err(|err| {
+                            if err.is_full() {
+                                DropReason::ProtocolBusy
+                            } else {
+                                DropReason::ProtocolNotOpen
+                            }
+                        }),
+                    None => {
+                        trace!("protocol {} not ready", proto_id);
+                        Err(DropReason::ProtocolNotOpen)
+                    }
+                };
+                match result {
+                    Ok(()) => {
+                        self.traffic.record_out(len);
+                        self.metrics.traffic().record_out(len);
+                    }
+                    Err(reason) => {
+                        if reason == DropReason::ProtocolBusy {
+                            self.metrics.channel_overflow();
+                        }
+                        self.traffic.record_dropped();
+                        self.metrics.traffic().record_dropped();
+                        self.dropped_messages.push_back((proto_id, reason));
+                        self.report_dropped();
+                    }
                 }
             }
             SessionEvent::SessionClose { .. } => {
                 self.close_session();
                 let _ = self.socket.shutdown();
             }
+            SessionEvent::Shutdown => {
+                debug!("session [{}] shutting down", self.id);
+                self.closing = true;
+                self.set_close_reason(CloseReason::LocalDisconnect);
+                // Remote can't open new streams after GoAway
+                let _ = self.socket.send_go_away();
+                // Drop the senders, the sub streams close after flushing the queued messages
+                self.sub_streams.clear();
+            }
+            SessionEvent::OpenProtocol { proto_id } => {
+                if self.closing || self.proto_streams.contains_key(&proto_id) {
+                    return;
+                }
+                let name = self
+                    .protocol_configs
+                    .read()
+                    .unwrap()
+                    .values()
+                    .find(|proto| proto.id() == proto_id)
+                    .map(|proto| proto.name());
+                match name {
+                    Some(name) => self.open_proto_stream(&name),
+                    None => debug!("session [{}] proto [{}] not found", self.id, proto_id),
+                }
+            }
+            SessionEvent::CloseProtocol { proto_id } => {
+                if let Some(stream_id) = self.proto_streams.get(&proto_id) {
+                    if let Some(sender) = self.sub_streams.get_mut(stream_id) {
+                        let _ = sender.try_send(ProtocolEvent::ProtocolClose {
+                            id: *stream_id,
+                            proto_id,
+                        });
+                    }
+                }
+            }
             _ => (),
         }
     }
 
+    /// Forward the queued messages to the protocol streams, higher priority first
+    fn forward_messages(&mut self, messages: &mut PriorityQueue<SessionEvent>) {
+        while let Some((_, event)) = messages.pop_front() {
+            self.handle_session_event(event);
+        }
+    }
+
+    /// Keep the first cause of the closing
+    fn set_close_reason(&mut self, reason: CloseReason) {
+        if self.close_reason.is_none() {
+            self.close_reason = Some(reason);
+        }
+    }
+
     /// Close session
     fn close_session(&mut self) {
-        let _ = self
-            .service_sender
-            .try_send(SessionEvent::SessionClose { id: self.id });
+        self.report_dropped();
+        let reason = self
+            .close_reason
+            .take()
+            .unwrap_or(CloseReason::LocalDisconnect);
+        let _ = self.service_sender.try_send(SessionEvent::SessionClose {
+            id: self.id,
+            reason,
+        });
 
         for (proto_id, mut sender) in self.sub_streams.drain() {
             let _ = sender.try_send(ProtocolEvent::ProtocolClose {
@@ -390,12 +643,9 @@ where
     }
 }
 
-impl<T, U> Stream for Session<T, U>
+impl<T> Stream for Session<T>
 where
     T: AsyncRead + AsyncWrite,
-    U: Decoder<Item = bytes::BytesMut> + Encoder<Item = bytes::Bytes> + Send + 'static,
-    <U as Decoder>::Error: error::Error + Into<io::Error>,
-    <U as Encoder>::Error: error::Error + Into<io::Error>,
 {
     type Item = ();
     type Error = io::Error;
@@ -407,18 +657,36 @@ where
             self.ty,
             self.sub_streams.len()
         );
-        loop {
+        self.report_dropped();
+
+        while !self.remote_closed {
             match self.socket.poll() {
                 Ok(Async::Ready(Some(sub_stream))) => self.handle_sub_stream(sub_stream),
                 Ok(Async::Ready(None)) => {
-                    self.close_session();
-                    return Ok(Async::Ready(None));
+                    self.remote_closed = true;
+                    self.closing = true;
+                    let reason = if self.socket.remote_go_away() {
+                        CloseReason::GoAway
+                    } else {
+                        CloseReason::RemoteEof
+                    };
+                    self.set_close_reason(reason);
                 }
                 Ok(Async::NotReady) => break,
                 Err(err) => {
                     warn!("sub stream error: {:?}", err);
-                    self.close_session();
-                    return Err(err);
+                    self.remote_closed = true;
+                    self.closing = true;
+                    let keepalive_timeout = err
+                        .get_ref()
+                        .and_then(|err| err.downcast_ref::<YamuxError>())
+                        .is_some_and(|err| matches!(err, YamuxError::KeepAliveTimeout));
+                    let reason = if keepalive_timeout {
+                        CloseReason::KeepaliveTimeout
+                    } else {
+                        CloseReason::Error(err)
+                    };
+                    self.set_close_reason(reason);
                 }
             }
         }
@@ -435,11 +703,23 @@ where
             }
         }
 
+        // The messages received in a round are forwarded by priority,
+        // the other events are handled after the messages before them
+        let mut messages = PriorityQueue::new();
         loop {
             match self.service_receiver.poll() {
-                Ok(Async::Ready(Some(event))) => self.handle_session_event(event),
+                Ok(Async::Ready(Some(event))) => match event {
+                    SessionEvent::ProtocolMessage { priority, .. } => {
+                        messages.push_back(priority, event)
+                    }
+                    event => {
+                        self.forward_messages(&mut messages);
+                        self.handle_session_event(event)
+                    }
+                },
                 Ok(Async::Ready(None)) => {
                     // Must drop by service
+                    self.forward_messages(&mut messages);
                     self.close_session();
                     return Ok(Async::Ready(None));
                 }
@@ -450,47 +730,76 @@ where
                 }
             }
         }
+        self.forward_messages(&mut messages);
+
+        if self.closing && self.proto_streams.is_empty() {
+            if !self.remote_closed {
+                // Write out the last frames of the closed sub streams
+                let _ = self.socket.poll();
+                let _ = self.socket.shutdown();
+            }
+            self.close_session();
+            return Ok(Async::Ready(None));
+        }
 
         Ok(Async::NotReady)
     }
 }
 
-pub(crate) struct SessionMeta<U> {
+pub(crate) struct SessionMeta {
     config: Config,
+    protocol_timeout: Duration,
     id: SessionId,
-    protocol_configs: Arc<HashMap<String, Box<dyn ProtocolMeta<U> + Send + Sync>>>,
+    protocol_configs: ProtocolConfigs,
     ty: SessionType,
-    remote_address: ::std::net::SocketAddr,
+    remote_address: Multiaddr,
     remote_public_key: Option<PublicKey>,
+    traffic: Arc<TrafficStats>,
+    metrics: Arc<Metrics>,
 }
 
-impl<U> SessionMeta<U>
-where
-    U: Decoder<Item = bytes::BytesMut> + Encoder<Item = bytes::Bytes> + Send + 'static,
-    <U as Decoder>::Error: error::Error + Into<io::Error>,
-    <U as Encoder>::Error: error::Error + Into<io::Error>,
-{
+impl SessionMeta {
     pub fn new(
         id: SessionId,
         ty: SessionType,
-        remote_address: ::std::net::SocketAddr,
+        remote_address: Multiaddr,
         remote_public_key: Option<PublicKey>,
     ) -> Self {
         SessionMeta {
             config: Config::default(),
+            protocol_timeout: Duration::from_secs(10),
             id,
             ty,
             remote_address,
-            protocol_configs: Arc::new(HashMap::new()),
+            protocol_configs: Arc::new(RwLock::new(HashMap::new())),
             remote_public_key,
+            traffic: Arc::new(TrafficStats::new()),
+            metrics: Arc::new(Metrics::default()),
         }
     }
 
-    pub fn protocol(
-        mut self,
-        config: Arc<HashMap<String, Box<dyn ProtocolMeta<U> + Send + Sync>>>,
-    ) -> Self {
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn protocol_timeout(mut self, timeout: Duration) -> Self {
+        self.protocol_timeout = timeout;
+        self
+    }
+
+    pub fn protocol(mut self, config: ProtocolConfigs) -> Self {
         self.protocol_configs = config;
         self
     }
+
+    pub fn traffic(mut self, traffic: Arc<TrafficStats>) -> Self {
+        self.traffic = traffic;
+        self
+    }
+
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
 }
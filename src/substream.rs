@@ -1,16 +1,13 @@
 use futures::{prelude::*, sync::mpsc};
 use log::{debug, error, warn};
-use std::collections::VecDeque;
-use std::{
-    error,
-    io::{self, ErrorKind},
-};
-use tokio::{
-    codec::{Decoder, Encoder, Framed},
-    prelude::AsyncWrite,
-};
-use yamux::StreamHandle;
+use secio::codec::stats::TrafficStats;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use tokio::{codec::Framed, prelude::AsyncWrite};
+use yamux::{Priority, PriorityQueue, StreamHandle};
 
+use crate::codec::Codec;
+use crate::metrics::Metrics;
 use crate::session::{ProtocolId, StreamId};
 
 /// Event generated/received by the protocol stream,
@@ -43,16 +40,25 @@ pub enum ProtocolEvent {
         proto_id: ProtocolId,
         /// Data
         data: bytes::Bytes,
+        /// Priority of sending
+        priority: Priority,
     },
 }
 
 /// Each custom protocol in a session corresponds to a sub stream
 /// Can be seen as the route of each protocol
-pub struct SubStream<U> {
-    sub_stream: Framed<StreamHandle, U>,
+pub struct SubStream {
+    sub_stream: Framed<StreamHandle, Box<dyn Codec>>,
     id: StreamId,
     proto_id: ProtocolId,
-    data_buf: VecDeque<bytes::Bytes>,
+    data_buf: PriorityQueue<bytes::Bytes>,
+    /// Close after the data buffer is flushed
+    closing: bool,
+    /// Frames of the protocol
+    traffic: Arc<TrafficStats>,
+    metrics: Arc<Metrics>,
+    /// Blocked by the yamux send window
+    stalled: bool,
 
     /// Send event to session
     event_sender: mpsc::Sender<ProtocolEvent>,
@@ -60,19 +66,16 @@ pub struct SubStream<U> {
     event_receiver: mpsc::Receiver<ProtocolEvent>,
 }
 
-impl<U> SubStream<U>
-where
-    U: Decoder<Item = bytes::BytesMut> + Encoder<Item = bytes::Bytes> + Send + 'static,
-    <U as Decoder>::Error: error::Error + Into<io::Error>,
-    <U as Encoder>::Error: error::Error + Into<io::Error>,
-{
+impl SubStream {
     /// New a protocol sub stream
     pub fn new(
-        sub_stream: Framed<StreamHandle, U>,
+        sub_stream: Framed<StreamHandle, Box<dyn Codec>>,
         event_sender: mpsc::Sender<ProtocolEvent>,
         event_receiver: mpsc::Receiver<ProtocolEvent>,
         id: StreamId,
         proto_id: ProtocolId,
+        traffic: Arc<TrafficStats>,
+        metrics: Arc<Metrics>,
     ) -> Self {
         SubStream {
             sub_stream,
@@ -80,30 +83,61 @@ where
             proto_id,
             event_sender,
             event_receiver,
-            data_buf: VecDeque::new(),
+            data_buf: PriorityQueue::new(),
+            closing: false,
+            traffic,
+            metrics,
+            stalled: false,
         }
     }
 
     /// Send data to the lower `yamux` sub stream
-    fn send_data(&mut self, data: bytes::Bytes) -> Poll<(), ()> {
-        self.data_buf.push_back(data);
-        while let Some(frame) = self.data_buf.pop_front() {
+    fn send_data(&mut self, priority: Priority, data: bytes::Bytes) -> Poll<(), ()> {
+        self.data_buf.push_back(priority, data);
+        self.flush_data()
+    }
+
+    /// Flush the data buffer to the lower `yamux` sub stream
+    fn flush_data(&mut self) -> Poll<(), ()> {
+        while let Some((priority, frame)) = self.data_buf.pop_front() {
+            if priority != self.sub_stream.get_ref().priority() {
+                // Write out the buffered frames with their priority first
+                match self.sub_stream.poll_complete() {
+                    Ok(Async::Ready(())) => self.sub_stream.get_mut().set_priority(priority),
+                    Ok(Async::NotReady) => {
+                        self.data_buf.push_front(priority, frame);
+                        self.check_stalled();
+                        return Ok(Async::NotReady);
+                    }
+                    Err(err) => {
+                        debug!("poll complete error: {:?}", err);
+                        self.traffic.record_dropped();
+                        return Err(());
+                    }
+                }
+            }
+            let len = frame.len();
             match self.sub_stream.start_send(frame) {
                 Ok(AsyncSink::NotReady(frame)) => {
                     debug!("framed_stream NotReady, frame: {:?}", frame);
-                    self.data_buf.push_front(frame);
+                    self.data_buf.push_front(priority, frame);
+                    self.check_stalled();
                     return Ok(Async::NotReady);
                 }
-                Ok(AsyncSink::Ready) => {}
+                Ok(AsyncSink::Ready) => self.traffic.record_out(len),
                 Err(err) => {
                     debug!("framed_stream send error: {:?}", err);
+                    self.traffic.record_dropped();
                     return Err(());
                 }
             }
         }
         match self.sub_stream.poll_complete() {
-            Ok(Async::NotReady) => return Ok(Async::NotReady),
-            Ok(Async::Ready(_)) => (),
+            Ok(Async::NotReady) => {
+                self.check_stalled();
+                return Ok(Async::NotReady);
+            }
+            Ok(Async::Ready(_)) => self.stalled = false,
             Err(err) => {
                 debug!("poll complete error: {:?}", err);
                 return Err(());
@@ -113,6 +147,15 @@ where
         Ok(Async::Ready(()))
     }
 
+    /// Count a stall once when the writing is blocked by the send window
+    fn check_stalled(&mut self) {
+        let stalled = self.sub_stream.get_ref().send_window() == 0;
+        if stalled && !self.stalled {
+            self.metrics.window_stall();
+        }
+        self.stalled = stalled;
+    }
+
     /// Close protocol sub stream
     fn close_proto_stream(&mut self) {
         let _ = self.event_sender.try_send(ProtocolEvent::ProtocolClose {
@@ -126,8 +169,8 @@ where
     /// Handling commands send by session
     fn handle_proto_event(&mut self, event: ProtocolEvent) -> Poll<Option<()>, ()> {
         match event {
-            ProtocolEvent::ProtocolMessage { data, .. } => {
-                match self.send_data(data) {
+            ProtocolEvent::ProtocolMessage { data, priority, .. } => {
+                match self.send_data(priority, data) {
                     Err(_) => {
                         // Whether it is a read send error or a flush error,
                         // the most essential problem is that there is a problem with the external network.
@@ -143,22 +186,14 @@ where
                     Ok(Async::Ready(_)) => (),
                 }
             }
-            ProtocolEvent::ProtocolClose { .. } => {
-                self.close_proto_stream();
-                return Ok(Async::Ready(None));
-            }
+            ProtocolEvent::ProtocolClose { .. } => self.closing = true,
             _ => (),
         }
         Ok(Async::Ready(Some(())))
     }
 }
 
-impl<U> Stream for SubStream<U>
-where
-    U: Decoder<Item = bytes::BytesMut> + Encoder<Item = bytes::Bytes> + Send + 'static,
-    <U as Decoder>::Error: error::Error + Into<io::Error>,
-    <U as Encoder>::Error: error::Error + Into<io::Error>,
-{
+impl Stream for SubStream {
     type Item = ();
     type Error = ();
 
@@ -167,12 +202,18 @@ where
             match self.sub_stream.poll() {
                 Ok(Async::Ready(Some(data))) => {
                     debug!("protocol [{}] receive data: {:?}", self.proto_id, data);
+                    self.traffic.record_in(data.len());
                     if let Err(e) = self.event_sender.try_send(ProtocolEvent::ProtocolMessage {
                         id: self.id,
                         proto_id: self.proto_id,
                         data: data.into(),
+                        priority: Priority::Normal,
                     }) {
                         error!("proto send to session error: {}", e);
+                        self.traffic.record_dropped();
+                        if e.is_full() {
+                            self.metrics.channel_overflow();
+                        }
                     }
                 }
                 Ok(Async::Ready(None)) => {
@@ -183,7 +224,7 @@ where
                 Ok(Async::NotReady) => break,
                 Err(err) => {
                     warn!("sub stream error: {:?}", err);
-                    match err.into().kind() {
+                    match err.kind() {
                         ErrorKind::BrokenPipe
                         | ErrorKind::ConnectionAborted
                         | ErrorKind::ConnectionReset
@@ -198,7 +239,7 @@ where
             }
         }
 
-        loop {
+        while !self.closing {
             match self.event_receiver.poll() {
                 Ok(Async::Ready(Some(event))) => match self.handle_proto_event(event) {
                     Ok(Async::NotReady) => break,
@@ -207,8 +248,7 @@ where
                 },
                 Ok(Async::Ready(None)) => {
                     // Must be session close
-                    self.close_proto_stream();
-                    return Ok(Async::Ready(None));
+                    self.closing = true;
                 }
                 Ok(Async::NotReady) => break,
                 Err(err) => {
@@ -218,6 +258,15 @@ where
             }
         }
 
+        if self.closing {
+            // Send out the pending data before close, give up on error
+            if let Ok(Async::NotReady) = self.flush_data() {
+                return Ok(Async::NotReady);
+            }
+            self.close_proto_stream();
+            return Ok(Async::Ready(None));
+        }
+
         Ok(Async::NotReady)
     }
 }
@@ -0,0 +1,272 @@
+use bytes::{Bytes, BytesMut};
+use futures::{future, prelude::*, sync::mpsc};
+use std::{
+    cmp::min,
+    collections::HashMap,
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::prelude::{AsyncRead, AsyncWrite};
+
+use crate::{
+    multiaddr::{Multiaddr, Protocol},
+    transport::{
+        transport_protocols, unsupported_address, BoxedConnection, Dialer, Incoming, Transport,
+    },
+};
+
+/// The number of buffered writes in each direction of a memory pipe
+const PIPE_BUFFER_SIZE: usize = 128;
+
+type Listeners = HashMap<String, mpsc::UnboundedSender<(MemoryStream, Multiaddr)>>;
+
+/// In-memory transport, connections are duplex pipes inside the current process.
+///
+/// All clones of a transport share the same virtual network, so services built
+/// with clones of one transport can listen on and dial each other by name,
+/// while services on different networks are isolated from each other.
+#[derive(Clone, Default)]
+pub struct MemoryTransport {
+    listeners: Arc<Mutex<Listeners>>,
+    next_dialer: Arc<AtomicUsize>,
+}
+
+impl MemoryTransport {
+    /// Create a new empty virtual network
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn can_handle(&self, address: &Multiaddr) -> bool {
+        memory_name(address).is_some()
+    }
+
+    fn listen(&self, address: Multiaddr) -> Result<(Multiaddr, Incoming), io::Error> {
+        let name = match memory_name(&address) {
+            Some(name) => name.to_owned(),
+            None => return Err(unsupported_address(&address)),
+        };
+        let mut listeners = self.listeners.lock().unwrap();
+        if listeners.contains_key(&name) {
+            return Err(io::ErrorKind::AddrInUse.into());
+        }
+        let (sender, receiver) = mpsc::unbounded();
+        listeners.insert(name.clone(), sender);
+
+        let incoming = MemoryIncoming {
+            name: name.clone(),
+            listeners: Arc::clone(&self.listeners),
+            receiver,
+        };
+        Ok((Multiaddr::from(Protocol::Memory(name)), Box::new(incoming)))
+    }
+
+    fn dial(&self, address: Multiaddr) -> Dialer {
+        let name = match memory_name(&address) {
+            Some(name) => name.to_owned(),
+            None => return Box::new(future::err(unsupported_address(&address))),
+        };
+        let (local, remote) = MemoryStream::pair();
+        // The dialer has no listen address, give it a unique name on this network
+        let dialer_address = Multiaddr::from(Protocol::Memory(format!(
+            "{}#{}",
+            name,
+            self.next_dialer.fetch_add(1, Ordering::SeqCst)
+        )));
+
+        let result = match self.listeners.lock().unwrap().get(&name) {
+            Some(sender) => sender
+                .unbounded_send((remote, dialer_address))
+                .map(|_| Box::new(local) as BoxedConnection)
+                .map_err(|_| io::ErrorKind::ConnectionRefused.into()),
+            None => Err(io::ErrorKind::ConnectionRefused.into()),
+        };
+        Box::new(future::result(result))
+    }
+}
+
+/// Get the name of `/memory/<name>` address
+fn memory_name(address: &Multiaddr) -> Option<&str> {
+    match transport_protocols(address) {
+        [Protocol::Memory(name)] => Some(name),
+        _ => None,
+    }
+}
+
+/// Inbound connections of a memory listener, the name is released on drop
+struct MemoryIncoming {
+    name: String,
+    listeners: Arc<Mutex<Listeners>>,
+    receiver: mpsc::UnboundedReceiver<(MemoryStream, Multiaddr)>,
+}
+
+impl Stream for MemoryIncoming {
+    type Item = (BoxedConnection, Multiaddr);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.receiver.poll() {
+            Ok(Async::Ready(Some((stream, address)))) => Ok(Async::Ready(Some((
+                Box::new(stream) as BoxedConnection,
+                address,
+            )))),
+            Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+}
+
+impl Drop for MemoryIncoming {
+    fn drop(&mut self) {
+        if let Ok(mut listeners) = self.listeners.lock() {
+            listeners.remove(&self.name);
+        }
+    }
+}
+
+/// One end of an in-memory duplex pipe
+pub struct MemoryStream {
+    /// Set to None after shutdown, the remote will read EOF
+    sender: Option<mpsc::Sender<Bytes>>,
+    receiver: mpsc::Receiver<Bytes>,
+    read_buf: BytesMut,
+}
+
+impl MemoryStream {
+    /// Create a pair of connected memory streams
+    pub fn pair() -> (MemoryStream, MemoryStream) {
+        let (a_sender, a_receiver) = mpsc::channel(PIPE_BUFFER_SIZE);
+        let (b_sender, b_receiver) = mpsc::channel(PIPE_BUFFER_SIZE);
+        (
+            MemoryStream {
+                sender: Some(a_sender),
+                receiver: b_receiver,
+                read_buf: BytesMut::default(),
+            },
+            MemoryStream {
+                sender: Some(b_sender),
+                receiver: a_receiver,
+                read_buf: BytesMut::default(),
+            },
+        )
+    }
+}
+
+impl io::Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read_buf.is_empty() {
+            match self.receiver.poll() {
+                Ok(Async::Ready(Some(data))) => self.read_buf.extend_from_slice(&data),
+                // Remote shutdown or dropped
+                Ok(Async::Ready(None)) => return Ok(0),
+                Ok(Async::NotReady) => return Err(io::ErrorKind::WouldBlock.into()),
+                Err(_) => return Err(io::ErrorKind::BrokenPipe.into()),
+            }
+        }
+
+        let n = min(buf.len(), self.read_buf.len());
+        let b = self.read_buf.split_to(n);
+        buf[..n].copy_from_slice(&b);
+        Ok(n)
+    }
+}
+
+impl io::Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let sender = match self.sender {
+            Some(ref mut sender) => sender,
+            None => return Err(io::ErrorKind::BrokenPipe.into()),
+        };
+        match sender.poll_ready() {
+            Ok(Async::Ready(_)) => (),
+            Ok(Async::NotReady) => return Err(io::ErrorKind::WouldBlock.into()),
+            Err(_) => return Err(io::ErrorKind::BrokenPipe.into()),
+        }
+        sender
+            .try_send(Bytes::from(buf))
+            .map(|_| buf.len())
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for MemoryStream {}
+
+impl AsyncWrite for MemoryStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.sender.take();
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryTransport;
+    use crate::{
+        channel::ChannelEvent,
+        multiaddr::{Multiaddr, Protocol},
+        service::{
+            tests::{builder, connect_channels},
+            Message,
+        },
+    };
+
+    #[test]
+    fn test_memory_services_communicate() {
+        let transport = MemoryTransport::new();
+        let (mut server, mut client) = connect_channels(builder(&transport), builder(&transport));
+
+        let id = client
+            .events
+            .by_ref()
+            .find_map(|event| match event {
+                Ok(ChannelEvent::ProtocolOpen { id, .. }) => Some(id),
+                _ => None,
+            })
+            .unwrap();
+        let message = Message {
+            id,
+            proto_id: 1,
+            data: b"hello memory".to_vec(),
+            ..Default::default()
+        };
+        client
+            .control
+            .send_message(Some(vec![id]), message)
+            .unwrap();
+        let received = server
+            .events
+            .find_map(|event| match event {
+                Ok(ChannelEvent::Message(message)) => Some(message.data),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(received, b"hello memory".to_vec());
+    }
+
+    #[test]
+    fn test_memory_address_in_use() {
+        let transport = MemoryTransport::new();
+        let (mut service_1, _) = builder(&transport).build_channel();
+        let (mut service_2, _) = builder(&transport).build_channel();
+        let (mut service_3, _) = builder(&MemoryTransport::new()).build_channel();
+        let address: Multiaddr = "/memory/node".parse().unwrap();
+
+        assert_eq!(
+            service_1.listen(address.clone()).unwrap(),
+            Multiaddr::from(Protocol::Memory("node".to_owned()))
+        );
+        assert!(service_2.listen(address.clone()).is_err());
+        // Different networks are isolated
+        assert!(service_3.listen(address).is_ok());
+    }
+}
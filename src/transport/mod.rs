@@ -0,0 +1,111 @@
+use futures::prelude::*;
+use std::io;
+use tokio::prelude::{AsyncRead, AsyncWrite};
+
+use crate::multiaddr::{Multiaddr, Protocol};
+
+mod memory;
+mod tcp;
+#[cfg(unix)]
+mod unix;
+mod ws;
+
+#[cfg(unix)]
+pub use self::unix::UnixTransport;
+pub use self::{
+    memory::{MemoryStream, MemoryTransport},
+    tcp::TcpTransport,
+    ws::{WsStream, WsTransport},
+};
+
+/// A duplex connection generated by the transport,
+/// anything that implements `AsyncRead + AsyncWrite` can be used
+pub trait Connection: AsyncRead + AsyncWrite + Send {}
+
+impl<T> Connection for T where T: AsyncRead + AsyncWrite + Send {}
+
+/// Boxed connection
+pub type BoxedConnection = Box<dyn Connection>;
+
+/// Inbound connections, each item is the connection and the remote address
+pub type Incoming = Box<dyn Stream<Item = (BoxedConnection, Multiaddr), Error = io::Error> + Send>;
+
+/// Outbound connection future
+pub type Dialer = Box<dyn Future<Item = BoxedConnection, Error = io::Error> + Send>;
+
+/// The carrier of the service's underlying connections.
+///
+/// The connection obtained from the transport will go through the secio handshake
+/// (if the key pair is set) and then be wrapped into a yamux session,
+/// so any reliable, ordered duplex stream can be used here.
+///
+/// A service can hold several transports, the first one that can handle
+/// the address is used to listen on or dial it.
+pub trait Transport {
+    /// Whether this transport can listen on and dial the given address
+    fn can_handle(&self, address: &Multiaddr) -> bool;
+
+    /// Listen on the given address.
+    ///
+    /// Return the actual listen address and the stream of inbound connections
+    fn listen(&self, address: Multiaddr) -> Result<(Multiaddr, Incoming), io::Error>;
+
+    /// Dial the given address, return a future of the outbound connection
+    fn dial(&self, address: Multiaddr) -> Dialer;
+}
+
+/// The protocols of the address except the trailing peer identity,
+/// which is not a concern of the transports
+pub(crate) fn transport_protocols(address: &Multiaddr) -> &[Protocol] {
+    match address.protocols() {
+        [rest @ .., Protocol::P2p(_)] => rest,
+        protocols => protocols,
+    }
+}
+
+/// The error returned when the transport does not support the given address
+pub(crate) fn unsupported_address(address: &Multiaddr) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("transport does not support address {}", address),
+    )
+}
+
+/// Transports held by the service, dispatch by address
+pub(crate) struct TransportSet {
+    /// Ordered by precedence
+    inner: Vec<Box<dyn Transport + Send>>,
+}
+
+impl TransportSet {
+    pub(crate) fn new(inner: Vec<Box<dyn Transport + Send>>) -> Self {
+        TransportSet { inner }
+    }
+
+    fn find(&self, address: &Multiaddr) -> Option<&(dyn Transport + Send)> {
+        self.inner
+            .iter()
+            .find(|transport| transport.can_handle(address))
+            .map(AsRef::as_ref)
+    }
+}
+
+impl Transport for TransportSet {
+    fn can_handle(&self, address: &Multiaddr) -> bool {
+        self.find(address).is_some()
+    }
+
+    fn listen(&self, address: Multiaddr) -> Result<(Multiaddr, Incoming), io::Error> {
+        match self.find(&address) {
+            Some(transport) => transport.listen(address),
+            None => Err(unsupported_address(&address)),
+        }
+    }
+
+    fn dial(&self, address: Multiaddr) -> Dialer {
+        match self.find(&address) {
+            Some(transport) => transport.dial(address),
+            None => Box::new(futures::future::err(unsupported_address(&address))),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use futures::{
+    future::{self, Either},
+    prelude::*,
+    sync::oneshot,
+};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    thread,
+};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{
+    multiaddr::{Multiaddr, Protocol},
+    transport::{
+        transport_protocols, unsupported_address, BoxedConnection, Dialer, Incoming, Transport,
+    },
+};
+
+/// Tcp transport, the default transport of service.
+///
+/// Handle `/ip4/<ip>/tcp/<port>`, `/ip6/<ip>/tcp/<port>`, and `/dns4/<name>/tcp/<port>`,
+/// `/dns6/<name>/tcp/<port>` which are resolved by the system resolver.
+///
+/// The domain names can only be dialed, not listened on
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    fn can_handle(&self, address: &Multiaddr) -> bool {
+        tcp_host(transport_protocols(address)).is_some()
+    }
+
+    fn listen(&self, address: Multiaddr) -> Result<(Multiaddr, Incoming), io::Error> {
+        let address = match tcp_host(transport_protocols(&address)) {
+            Some(host) => host.listen_address()?,
+            None => return Err(unsupported_address(&address)),
+        };
+        let tcp = TcpListener::bind(&address)?;
+        let listen_address = tcp.local_addr()?;
+        let incoming = tcp.incoming().and_then(|socket| {
+            let address = socket.peer_addr()?;
+            Ok((
+                Box::new(socket) as BoxedConnection,
+                Multiaddr::from(address),
+            ))
+        });
+        Ok((Multiaddr::from(listen_address), Box::new(incoming)))
+    }
+
+    fn dial(&self, address: Multiaddr) -> Dialer {
+        match tcp_host(transport_protocols(&address)) {
+            Some(host) => Box::new(
+                host.resolve()
+                    .and_then(|address| TcpStream::connect(&address))
+                    .map(|socket| Box::new(socket) as BoxedConnection),
+            ),
+            None => Box::new(future::err(unsupported_address(&address))),
+        }
+    }
+}
+
+/// The host and port of a tcp address
+pub(crate) enum TcpHost<'a> {
+    /// Ip address
+    Ip(IpAddr, u16),
+    /// Domain name, and whether to resolve it to ipv6
+    Dns(&'a str, u16, bool),
+}
+
+impl<'a> TcpHost<'a> {
+    /// Resolve to socket address.
+    ///
+    /// Domain names are resolved by the system resolver on a new thread, so the lookup
+    /// doesn't stall the other tasks on any runtime
+    pub(crate) fn resolve(&self) -> impl Future<Item = SocketAddr, Error = io::Error> + Send {
+        let (name, port, ipv6) = match *self {
+            TcpHost::Ip(ip, port) => return Either::A(future::ok(SocketAddr::new(ip, port))),
+            TcpHost::Dns(name, port, ipv6) => (name.to_owned(), port, ipv6),
+        };
+        let (sender, receiver) = oneshot::channel();
+        thread::spawn(move || {
+            let _ = sender.send(resolve_dns(&name, port, ipv6));
+        });
+        Either::B(
+            receiver
+                .map_err(|_| io::Error::new(io::ErrorKind::Other, "dns resolver thread panicked"))
+                .and_then(|result| result),
+        )
+    }
+
+    /// The socket address to listen on, domain names are not supported
+    pub(crate) fn listen_address(&self) -> Result<SocketAddr, io::Error> {
+        match *self {
+            TcpHost::Ip(ip, port) => Ok(SocketAddr::new(ip, port)),
+            TcpHost::Dns(name, _, _) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("can't listen on domain name {}", name),
+            )),
+        }
+    }
+}
+
+/// Resolve the domain name by the system resolver, it's blocking
+fn resolve_dns(name: &str, port: u16, ipv6: bool) -> Result<SocketAddr, io::Error> {
+    (name, port)
+        .to_socket_addrs()?
+        .find(|address| address.is_ipv6() == ipv6)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                format!("can't resolve {}", name),
+            )
+        })
+}
+
+/// Get the host of `<ip4|ip6|dns4|dns6>/tcp` protocols
+pub(crate) fn tcp_host(protocols: &[Protocol]) -> Option<TcpHost<'_>> {
+    match protocols {
+        [Protocol::Ip4(ip), Protocol::Tcp(port)] => Some(TcpHost::Ip(IpAddr::V4(*ip), *port)),
+        [Protocol::Ip6(ip), Protocol::Tcp(port)] => Some(TcpHost::Ip(IpAddr::V6(*ip), *port)),
+        [Protocol::Dns4(name), Protocol::Tcp(port)] => Some(TcpHost::Dns(name, *port, false)),
+        [Protocol::Dns6(name), Protocol::Tcp(port)] => Some(TcpHost::Dns(name, *port, true)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TcpTransport;
+    use crate::{multiaddr::Multiaddr, transport::Transport};
+    use futures::prelude::*;
+    use tokio::runtime::{current_thread, Runtime};
+
+    #[test]
+    fn test_dial_dns() {
+        let mut runtime = Runtime::new().unwrap();
+        let (address, incoming) = TcpTransport
+            .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .unwrap();
+        let port = address.to_string().rsplit('/').next().unwrap().to_owned();
+        let dns: Multiaddr = format!("/dns4/localhost/tcp/{}", port).parse().unwrap();
+
+        assert!(TcpTransport.listen(dns.clone()).is_err());
+        runtime.block_on(TcpTransport.dial(dns.clone())).unwrap();
+        // Resolved without the thread pool
+        current_thread::Runtime::new()
+            .unwrap()
+            .block_on(TcpTransport.dial(dns))
+            .unwrap();
+        let accepted = runtime.block_on(incoming.take(2).collect());
+        assert_eq!(accepted.unwrap().len(), 2);
+    }
+}
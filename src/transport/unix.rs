@@ -0,0 +1,132 @@
+use futures::{future, prelude::*};
+use std::{fs, io};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::{
+    multiaddr::{Multiaddr, Protocol},
+    transport::{
+        transport_protocols, unsupported_address, BoxedConnection, Dialer, Incoming, Transport,
+    },
+};
+
+/// Unix domain socket transport
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UnixTransport;
+
+impl Transport for UnixTransport {
+    fn can_handle(&self, address: &Multiaddr) -> bool {
+        unix_path(address).is_some()
+    }
+
+    fn listen(&self, address: Multiaddr) -> Result<(Multiaddr, Incoming), io::Error> {
+        let path = match unix_path(&address) {
+            Some(path) => path.to_owned(),
+            None => return Err(unsupported_address(&address)),
+        };
+        let listener = UnixListener::bind(&path)?;
+        let listen_path = path.clone();
+        let incoming = listener.incoming().map(move |socket| {
+            // Dialers are usually unnamed, use the listen path instead
+            let path = socket
+                .peer_addr()
+                .ok()
+                .and_then(|address| {
+                    address
+                        .as_pathname()
+                        .map(|path| path.to_string_lossy().into_owned())
+                })
+                .unwrap_or_else(|| listen_path.clone());
+            let address = Multiaddr::from(Protocol::Unix(path));
+            (Box::new(socket) as BoxedConnection, address)
+        });
+        let incoming = UnixIncoming {
+            inner: Box::new(incoming),
+            path: path.clone(),
+        };
+        Ok((Multiaddr::from(Protocol::Unix(path)), Box::new(incoming)))
+    }
+
+    fn dial(&self, address: Multiaddr) -> Dialer {
+        match unix_path(&address) {
+            Some(path) => Box::new(
+                UnixStream::connect(path).map(|socket| Box::new(socket) as BoxedConnection),
+            ),
+            None => Box::new(future::err(unsupported_address(&address))),
+        }
+    }
+}
+
+/// Inbound connections of a unix socket, the socket file is removed on drop
+struct UnixIncoming {
+    inner: Incoming,
+    path: String,
+}
+
+impl Stream for UnixIncoming {
+    type Item = (BoxedConnection, Multiaddr);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.inner.poll()
+    }
+}
+
+impl Drop for UnixIncoming {
+    fn drop(&mut self) {
+        // Or the path can't be bound again after restart
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Get the path of `/unix/<path>` address
+fn unix_path(address: &Multiaddr) -> Option<&str> {
+    match transport_protocols(address) {
+        [Protocol::Unix(path)] => Some(path),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnixTransport;
+    use crate::{
+        multiaddr::{Multiaddr, Protocol},
+        service::tests::{builder, connect_channels_on, session_address},
+        transport::{MemoryTransport, Transport},
+    };
+    use std::{env, process};
+
+    #[test]
+    fn test_unix_services_connect() {
+        let path = env::temp_dir().join(format!("p2p-unix-test-{}.sock", process::id()));
+        let _ = std::fs::remove_file(&path);
+        let transport = MemoryTransport::new();
+        let address = Multiaddr::from(Protocol::Unix(path.to_string_lossy().into_owned()));
+        let (mut server, mut client) =
+            connect_channels_on(builder(&transport), builder(&transport), address);
+
+        for end in [&mut server, &mut client].iter_mut() {
+            let address = session_address(end);
+            match address.protocols() {
+                [Protocol::Unix(_)] => (),
+                _ => panic!("unexpected address {}", address),
+            }
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_socket_file_removed() {
+        let path = env::temp_dir().join(format!("p2p-unix-drop-{}.sock", process::id()));
+        let _ = std::fs::remove_file(&path);
+        let address = Multiaddr::from(Protocol::Unix(path.to_string_lossy().into_owned()));
+
+        let (_, incoming) = UnixTransport.listen(address.clone()).unwrap();
+        assert!(path.exists());
+        drop(incoming);
+        assert!(!path.exists());
+        // The path can be bound again
+        let (_, incoming) = UnixTransport.listen(address).unwrap();
+        drop(incoming);
+    }
+}
@@ -0,0 +1,287 @@
+use bytes::BytesMut;
+use futures::{future, prelude::*};
+use log::debug;
+use std::{cmp::min, io, time::Duration};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    prelude::{AsyncRead, AsyncWrite, FutureExt},
+};
+use tungstenite::{
+    client::IntoClientRequest,
+    handshake::{
+        client::ClientHandshake,
+        server::{NoCallback, ServerHandshake},
+        HandshakeError, HandshakeRole, MidHandshake,
+    },
+    protocol::WebSocketConfig,
+    Error as WsError, Message, WebSocket,
+};
+
+use crate::{
+    multiaddr::{Multiaddr, Protocol},
+    transport::{
+        tcp::{tcp_host, TcpHost},
+        transport_protocols, unsupported_address, BoxedConnection, Dialer, Incoming, Transport,
+    },
+};
+
+/// The number of outgoing frames buffered before writes return `WouldBlock`
+const MAX_SEND_QUEUE: usize = 64;
+/// The number of inbound websocket handshakes in progress at the same time
+const MAX_PENDING_HANDSHAKES: usize = 16;
+/// Timeout of the websocket handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// WebSocket transport, the data is carried by binary frames.
+///
+/// Handle the tcp addresses ending with `/ws`, such as `/ip4/127.0.0.1/tcp/1337/ws`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WsTransport;
+
+impl Transport for WsTransport {
+    fn can_handle(&self, address: &Multiaddr) -> bool {
+        ws_host(address).is_some()
+    }
+
+    fn listen(&self, address: Multiaddr) -> Result<(Multiaddr, Incoming), io::Error> {
+        let address = match ws_host(&address) {
+            Some(host) => host.listen_address()?,
+            None => return Err(unsupported_address(&address)),
+        };
+        let tcp = TcpListener::bind(&address)?;
+        let listen_address = Multiaddr::from(tcp.local_addr()?).with(Protocol::Ws);
+        // Only the errors of the listener are yielded, the failed handshakes are skipped
+        let incoming = tcp
+            .incoming()
+            .map(|socket| {
+                future::result(socket.peer_addr())
+                    .and_then(|address| {
+                        let address = Multiaddr::from(address).with(Protocol::Ws);
+                        WsHandshake(Some(ServerHandshake::start(socket, NoCallback, config())))
+                            .timeout(HANDSHAKE_TIMEOUT)
+                            .map_err(|err| {
+                                err.into_inner()
+                                    .unwrap_or_else(|| io::ErrorKind::TimedOut.into())
+                            })
+                            .map(move |stream| {
+                                (Box::new(WsStream::new(stream)) as BoxedConnection, address)
+                            })
+                    })
+                    .then(|result| match result {
+                        Ok(connection) => Ok(Some(connection)),
+                        Err(err) => {
+                            debug!("inbound websocket handshake failed: {}", err);
+                            Ok(None)
+                        }
+                    })
+            })
+            .buffer_unordered(MAX_PENDING_HANDSHAKES)
+            .filter_map(|connection| connection);
+        Ok((listen_address, Box::new(incoming)))
+    }
+
+    fn dial(&self, address: Multiaddr) -> Dialer {
+        let host = match ws_host(&address) {
+            Some(host) => host,
+            None => return Box::new(future::err(unsupported_address(&address))),
+        };
+        let url = match host {
+            TcpHost::Ip(ip, port) if ip.is_ipv6() => format!("ws://[{}]:{}/", ip, port),
+            TcpHost::Ip(ip, port) => format!("ws://{}:{}/", ip, port),
+            TcpHost::Dns(name, port, _) => format!("ws://{}:{}/", name, port),
+        };
+        let task = host
+            .resolve()
+            .and_then(|address| TcpStream::connect(&address))
+            .and_then(move |socket| {
+                url.into_client_request()
+                    .and_then(|request| ClientHandshake::start(socket, request, config()))
+                    .map(|handshake| WsHandshake(Some(handshake)))
+                    .map_err(into_io_error)
+            })
+            .and_then(|handshake| handshake)
+            .map(|(stream, _)| Box::new(WsStream::new(stream)) as BoxedConnection);
+        Box::new(task)
+    }
+}
+
+fn config() -> Option<WebSocketConfig> {
+    Some(WebSocketConfig {
+        max_send_queue: Some(MAX_SEND_QUEUE),
+        ..Default::default()
+    })
+}
+
+/// Get the tcp host of `<ip4|ip6|dns4|dns6>/tcp/ws` address
+fn ws_host(address: &Multiaddr) -> Option<TcpHost<'_>> {
+    match transport_protocols(address) {
+        [host @ .., Protocol::Ws] => tcp_host(host),
+        _ => None,
+    }
+}
+
+fn into_io_error(error: WsError) -> io::Error {
+    match error {
+        WsError::Io(error) => error,
+        WsError::ConnectionClosed | WsError::AlreadyClosed => io::ErrorKind::BrokenPipe.into(),
+        error => io::Error::new(io::ErrorKind::Other, error.to_string()),
+    }
+}
+
+/// Drive the blocking style websocket handshake on the non-blocking socket
+struct WsHandshake<R: HandshakeRole>(Option<MidHandshake<R>>);
+
+impl<R: HandshakeRole> Future for WsHandshake<R> {
+    type Item = R::FinalResult;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let handshake = self.0.take().expect("poll a finished websocket handshake");
+        match handshake.handshake() {
+            Ok(result) => Ok(Async::Ready(result)),
+            // The socket returns `WouldBlock`, this task will be notified when it's ready
+            Err(HandshakeError::Interrupted(handshake)) => {
+                self.0 = Some(handshake);
+                Ok(Async::NotReady)
+            }
+            Err(HandshakeError::Failure(error)) => Err(into_io_error(error)),
+        }
+    }
+}
+
+/// Byte stream over the binary frames of websocket
+pub struct WsStream {
+    inner: WebSocket<TcpStream>,
+    read_buf: BytesMut,
+}
+
+impl WsStream {
+    fn new(inner: WebSocket<TcpStream>) -> Self {
+        WsStream {
+            inner,
+            read_buf: BytesMut::default(),
+        }
+    }
+}
+
+impl io::Read for WsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read_buf.is_empty() {
+            match self.inner.read_message() {
+                Ok(Message::Binary(data)) => self.read_buf.extend_from_slice(&data),
+                Ok(Message::Text(data)) => self.read_buf.extend_from_slice(data.as_bytes()),
+                // Replied by tungstenite automatically
+                Ok(Message::Ping(_)) | Ok(Message::Pong(_)) => (),
+                Ok(Message::Close(_)) => return Ok(0),
+                Err(WsError::ConnectionClosed) | Err(WsError::AlreadyClosed) => return Ok(0),
+                Err(error) => return Err(into_io_error(error)),
+            }
+        }
+
+        let n = min(buf.len(), self.read_buf.len());
+        let b = self.read_buf.split_to(n);
+        buf[..n].copy_from_slice(&b);
+        Ok(n)
+    }
+}
+
+impl io::Write for WsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.inner.write_message(Message::Binary(buf.to_vec())) {
+            Ok(()) => Ok(buf.len()),
+            // The frame has been queued, it will be sent on the next write or flush
+            Err(WsError::Io(ref error)) if error.kind() == io::ErrorKind::WouldBlock => {
+                Ok(buf.len())
+            }
+            Err(WsError::SendQueueFull(_)) => Err(io::ErrorKind::WouldBlock.into()),
+            Err(error) => Err(into_io_error(error)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.write_pending().map_err(into_io_error)
+    }
+}
+
+impl AsyncRead for WsStream {}
+
+impl AsyncWrite for WsStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        let result = if self.inner.can_write() {
+            self.inner.close(None)
+        } else {
+            self.inner.write_pending()
+        };
+        match result {
+            Ok(()) | Err(WsError::ConnectionClosed) | Err(WsError::AlreadyClosed) => {
+                Ok(Async::Ready(()))
+            }
+            Err(WsError::Io(ref error)) if error.kind() == io::ErrorKind::WouldBlock => {
+                Ok(Async::NotReady)
+            }
+            Err(error) => Err(into_io_error(error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WsTransport;
+    use crate::{
+        multiaddr::{multiaddr_to_socketaddr, Protocol},
+        service::tests::{builder, connect_channels_on, session_address},
+        transport::{MemoryTransport, Transport},
+    };
+    use futures::prelude::*;
+    use std::{io::Write, thread};
+    use tungstenite::Message;
+
+    #[test]
+    fn test_ws_services_connect() {
+        let transport = MemoryTransport::new();
+        let (mut server, mut client) = connect_channels_on(
+            builder(&transport),
+            builder(&transport),
+            "/ip4/127.0.0.1/tcp/0/ws".parse().unwrap(),
+        );
+
+        for end in [&mut server, &mut client].iter_mut() {
+            let address = session_address(end);
+            assert_eq!(address.protocols().last(), Some(&Protocol::Ws));
+        }
+    }
+
+    #[test]
+    fn test_ws_binary_frames() {
+        let (address, incoming) = WsTransport
+            .listen("/ip4/127.0.0.1/tcp/0/ws".parse().unwrap())
+            .unwrap();
+        let echo = incoming
+            .into_future()
+            .map_err(|_| ())
+            .and_then(|(connection, _)| {
+                let (reader, writer) = tokio::io::AsyncRead::split(connection.unwrap().0);
+                tokio::io::copy(reader, writer).map(|_| ()).map_err(|_| ())
+            });
+        thread::spawn(|| tokio::run(echo));
+
+        let mut address = address;
+        assert_eq!(address.pop(), Some(Protocol::Ws));
+        let address = multiaddr_to_socketaddr(&address).unwrap();
+        // A plain tcp probe is skipped by the listener
+        let mut probe = std::net::TcpStream::connect(address).unwrap();
+        probe.write_all(b"hello\r\n\r\n").unwrap();
+        drop(probe);
+
+        let socket = std::net::TcpStream::connect(address).unwrap();
+        let url = format!("ws://{}/", address);
+        let (mut client, _) = tungstenite::client(url, socket).unwrap();
+        client
+            .write_message(Message::Binary(b"hello ws".to_vec()))
+            .unwrap();
+        assert_eq!(
+            client.read_message().unwrap(),
+            Message::Binary(b"hello ws".to_vec())
+        );
+    }
+}
@@ -15,6 +15,7 @@ pub const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(6);
 pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Configuration of session and stream
+#[derive(Clone, Copy, Debug)]
 pub struct Config {
     /// AcceptBacklog is used to limit how many streams may be
     /// waiting an accept.
@@ -31,6 +32,7 @@ pub struct Config {
     /// we which will suspect a problem with the underlying connection and
     /// close it. This is only applied to writes, where's there's generally
     /// an expectation that things will move along quickly.
+    /// It's also the deadline of the keep alive pings.
     pub connection_write_timeout: Duration,
 
     /// Max stream count
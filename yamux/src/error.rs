@@ -1,5 +1,7 @@
 //! The error types
 
+use std::{error, fmt};
+
 /// The error types
 #[derive(Debug)]
 pub enum Error {
@@ -52,3 +54,27 @@ pub enum Error {
     /// Remote sub stream is closed, but local can still send data to remote
     SubStreamRemoteClosing,
 }
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            Error::InvalidVersion => "invalid protocol version",
+            Error::InvalidMsgType => "invalid msg type",
+            Error::SessionShutdown => "session shutdown",
+            Error::StreamsExhausted => "streams exhausted",
+            Error::DuplicateStream => "duplicate stream initiated",
+            Error::RecvWindowExceeded => "recv window exceeded",
+            Error::Timeout => "i/o deadline reached",
+            Error::StreamClosed => "stream closed",
+            Error::UnexpectedFlag => "unexpected flag",
+            Error::RemoteGoAway => "remote end is not accepting connections",
+            Error::ConnectionReset => "connection reset",
+            Error::ConnectionWriteTimeout => "connection write timeout",
+            Error::KeepAliveTimeout => "keepalive timeout",
+            Error::SubStreamRemoteClosing => "sub stream remote closing",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+impl error::Error for Error {}
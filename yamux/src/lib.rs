@@ -10,6 +10,8 @@ pub mod config;
 pub mod error;
 // Frame module
 pub mod frame;
+// Priority module
+mod priority;
 // Session module
 pub mod session;
 // Stream module
@@ -18,7 +20,13 @@ pub mod stream;
 // Stream ID type
 pub(crate) type StreamId = u32;
 
-pub use crate::{config::Config, error::Error, session::Session, stream::StreamHandle};
+pub use crate::{
+    config::Config, error::Error, priority::Priority, session::Session, stream::StreamHandle,
+};
+
+// Shared with the protocol streams of p2p, not a part of the yamux API
+#[doc(hidden)]
+pub use crate::priority::PriorityQueue;
 
 // Latest Protocol Version
 pub(crate) const PROTOCOL_VERSION: u8 = 0;
@@ -0,0 +1,189 @@
+//! Priorities of the outbound data and the queue draining them
+
+use std::collections::VecDeque;
+
+/// After this many pops from the higher lanes, a waiting lower lane is served once
+const STARVATION_LIMIT: usize = 16;
+
+/// Priority of the outbound data, higher priority data is sent first
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum Priority {
+    /// Time-critical data, such as consensus messages
+    High,
+    /// Default priority
+    #[default]
+    Normal,
+    /// Bulk data, such as sync responses
+    Low,
+}
+
+impl Priority {
+    const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    fn lane(self) -> usize {
+        match self {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2,
+        }
+    }
+}
+
+/// A FIFO queue for each priority, the higher lanes are drained first.
+///
+/// A lower lane skipped `STARVATION_LIMIT` times in a row is served once, so bulk data still moves
+/// when the higher lanes are busy
+#[derive(Debug)]
+pub struct PriorityQueue<T> {
+    lanes: [VecDeque<T>; 3],
+    /// Times each lane is skipped while not empty
+    skipped: [usize; 3],
+}
+
+impl<T> PriorityQueue<T> {
+    /// New an empty queue
+    pub fn new() -> Self {
+        PriorityQueue {
+            lanes: Default::default(),
+            skipped: [0; 3],
+        }
+    }
+
+    /// Push an item to the back of its lane
+    pub fn push_back(&mut self, priority: Priority, item: T) {
+        self.lanes[priority.lane()].push_back(item);
+    }
+
+    /// Put a popped item back to the front of its lane
+    pub fn push_front(&mut self, priority: Priority, item: T) {
+        self.lanes[priority.lane()].push_front(item);
+    }
+
+    /// Pop the next item to send
+    pub fn pop_front(&mut self) -> Option<(Priority, T)> {
+        // Serve the lowest starved lane first
+        let starved = Priority::ALL.iter().rev().find(|priority| {
+            let lane = priority.lane();
+            self.skipped[lane] >= STARVATION_LIMIT && !self.lanes[lane].is_empty()
+        });
+        let priority = match starved {
+            Some(priority) => *priority,
+            None => *Priority::ALL
+                .iter()
+                .find(|priority| !self.lanes[priority.lane()].is_empty())?,
+        };
+
+        for other in Priority::ALL.iter() {
+            let lane = other.lane();
+            if lane == priority.lane() || self.lanes[lane].is_empty() {
+                self.skipped[lane] = 0;
+            } else if lane > priority.lane() {
+                self.skipped[lane] += 1;
+            }
+        }
+        self.lanes[priority.lane()]
+            .pop_front()
+            .map(|item| (priority, item))
+    }
+
+    /// Number of the queued items
+    pub fn len(&self) -> usize {
+        self.lanes.iter().map(VecDeque::len).sum()
+    }
+
+    /// Whether the queue is empty
+    pub fn is_empty(&self) -> bool {
+        self.lanes.iter().all(VecDeque::is_empty)
+    }
+}
+
+impl<T> Default for PriorityQueue<T> {
+    fn default() -> Self {
+        PriorityQueue::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Priority, PriorityQueue, STARVATION_LIMIT};
+
+    fn drain(queue: &mut PriorityQueue<usize>) -> Vec<(Priority, usize)> {
+        let mut items = Vec::new();
+        while let Some(item) = queue.pop_front() {
+            items.push(item);
+        }
+        items
+    }
+
+    #[test]
+    fn test_pop_order() {
+        let mut queue = PriorityQueue::new();
+        queue.push_back(Priority::Low, 0);
+        queue.push_back(Priority::Normal, 1);
+        queue.push_back(Priority::High, 2);
+        queue.push_back(Priority::Normal, 3);
+        queue.push_back(Priority::High, 4);
+        assert_eq!(queue.len(), 5);
+
+        assert_eq!(
+            drain(&mut queue),
+            vec![
+                (Priority::High, 2),
+                (Priority::High, 4),
+                (Priority::Normal, 1),
+                (Priority::Normal, 3),
+                (Priority::Low, 0),
+            ]
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_starved_lane() {
+        let mut queue = PriorityQueue::new();
+        for i in 0..STARVATION_LIMIT + 4 {
+            queue.push_back(Priority::High, i);
+        }
+        queue.push_back(Priority::Low, 100);
+        queue.push_back(Priority::Low, 101);
+
+        let lanes: Vec<Priority> = drain(&mut queue)
+            .into_iter()
+            .map(|(priority, _)| priority)
+            .collect();
+        let mut expected = vec![Priority::High; STARVATION_LIMIT];
+        expected.push(Priority::Low);
+        expected.extend(vec![Priority::High; 4]);
+        expected.push(Priority::Low);
+        assert_eq!(lanes, expected);
+    }
+
+    #[test]
+    fn test_push_front() {
+        let mut queue = PriorityQueue::new();
+        queue.push_back(Priority::Normal, 0);
+        queue.push_back(Priority::Normal, 1);
+        queue.push_back(Priority::Normal, 2);
+        queue.push_back(Priority::High, 3);
+
+        let first = queue.pop_front().unwrap();
+        let second = queue.pop_front().unwrap();
+        assert_eq!(
+            (first, second),
+            ((Priority::High, 3), (Priority::Normal, 0))
+        );
+        // Put back in the reverse order of popping
+        queue.push_front(second.0, second.1);
+        queue.push_front(first.0, first.1);
+
+        assert_eq!(
+            drain(&mut queue),
+            vec![
+                (Priority::High, 3),
+                (Priority::Normal, 0),
+                (Priority::Normal, 1),
+                (Priority::Normal, 2),
+            ]
+        );
+    }
+}
@@ -18,8 +18,9 @@ use crate::{
     config::Config,
     error::Error,
     frame::{Flag, Flags, Frame, FrameCodec, GoAwayCode, Type},
+    priority::{Priority, PriorityQueue},
     stream::{StreamEvent, StreamHandle, StreamState},
-    StreamId,
+    StreamId, RESERVED_STREAM_ID,
 };
 
 /// The session
@@ -60,7 +61,10 @@ pub struct Session<T> {
     // The StreamHandle not yet been polled
     pending_streams: VecDeque<StreamHandle>,
 
-    pending_frames: VecDeque<Frame>,
+    pending_frames: PriorityQueue<Frame>,
+    // The lane and the number of the pending frames of each stream,
+    // the frames of a stream stay in one lane to keep them in order
+    pending_lanes: FnvHashMap<StreamId, (Priority, usize)>,
 
     // For receive events from sub streams (for clone to new stream)
     event_sender: Sender<StreamEvent>,
@@ -111,7 +115,8 @@ where
             streams: FnvHashMap::default(),
             inflight: FnvHashSet::default(),
             pending_streams: VecDeque::default(),
-            pending_frames: VecDeque::default(),
+            pending_frames: PriorityQueue::default(),
+            pending_lanes: FnvHashMap::default(),
             event_sender,
             event_receiver,
             keepalive_future,
@@ -134,8 +139,15 @@ where
         if self.shutdown {
             return Ok(Async::Ready(()));
         }
+        // The pending frames are dropped once the session is dead, send the GoAway first
+        if !self.local_go_away {
+            self.local_go_away = true;
+            let frame = Frame::new_go_away(GoAwayCode::Normal);
+            self.pending_frames.push_back(Priority::High, frame);
+        }
+        try_ready!(self.send_all());
         self.shutdown = true;
-        self.send_go_away()
+        self.framed_stream.close()
     }
 
     // Send all pending frames to remote streams
@@ -145,6 +157,11 @@ where
         Ok(())
     }
 
+    /// Whether the remote sent a GoAway, it doesn't accept new streams after that
+    pub fn remote_go_away(&self) -> bool {
+        self.remote_go_away
+    }
+
     fn is_dead(&self) -> bool {
         self.shutdown || self.eof
     }
@@ -158,7 +175,8 @@ where
             }
         };
         let frame = Frame::new_ping(Flags::from(flag), ping_id);
-        self.send_frame(frame).map(|_| Async::Ready(ping_id))
+        self.send_frame(frame, Priority::High)
+            .map(|_| Async::Ready(ping_id))
     }
 
     /// GoAway can be used to prevent accepting further
@@ -166,7 +184,7 @@ where
     pub fn send_go_away(&mut self) -> Poll<(), io::Error> {
         self.local_go_away = true;
         let frame = Frame::new_go_away(GoAwayCode::Normal);
-        self.send_frame(frame)
+        self.send_frame(frame, Priority::High)
     }
 
     /// Open a new stream to remote session
@@ -183,6 +201,17 @@ where
     }
 
     fn keep_alive(&mut self, ping_at: Instant) -> Poll<(), io::Error> {
+        // Like go-yamux, a ping unanswered within the write timeout closes the session
+        if let Some(sent_at) = self.pings.values().min() {
+            if ping_at.duration_since(*sent_at) > self.config.connection_write_timeout {
+                warn!("[{:?}] keep_alive ping timeout", self.ty);
+                self.streams.clear();
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    Error::KeepAliveTimeout,
+                ));
+            }
+        }
         let ping_id = try_ready!(self.send_ping(None));
         debug!("[{:?}] sent keep_alive ping (id={:?})", self.ty, ping_id);
         self.pings.insert(ping_id, ping_at);
@@ -216,18 +245,27 @@ where
 
     #[inline]
     fn send_all(&mut self) -> Poll<(), io::Error> {
-        while let Some(frame) = self.pending_frames.pop_front() {
+        while let Some((priority, frame)) = self.pending_frames.pop_front() {
             if self.is_dead() {
                 break;
             }
 
+            let stream_id = frame.stream_id();
             match self.framed_stream.start_send(frame) {
                 Ok(AsyncSink::NotReady(frame)) => {
                     debug!("[{:?}] framed_stream NotReady, frame: {:?}", self.ty, frame);
-                    self.pending_frames.push_front(frame);
+                    self.pending_frames.push_front(priority, frame);
                     return Ok(Async::NotReady);
                 }
-                Ok(AsyncSink::Ready) => {}
+                Ok(AsyncSink::Ready) => {
+                    let sent = self.pending_lanes.get_mut(&stream_id).map(|(_, count)| {
+                        *count -= 1;
+                        *count == 0
+                    });
+                    if sent == Some(true) {
+                        self.pending_lanes.remove(&stream_id);
+                    }
+                }
                 Err(err) => {
                     debug!("[{:?}] framed_stream error: {:?}", self.ty, err);
                     return Err(err);
@@ -239,9 +277,17 @@ where
         Ok(Async::Ready(()))
     }
 
-    fn send_frame(&mut self, frame: Frame) -> Poll<(), io::Error> {
+    fn send_frame(&mut self, frame: Frame, priority: Priority) -> Poll<(), io::Error> {
         debug!("[{:?}] Session::send_frame({:?})", self.ty, frame);
-        self.pending_frames.push_back(frame);
+        let stream_id = frame.stream_id();
+        let priority = if stream_id == RESERVED_STREAM_ID {
+            priority
+        } else {
+            let lane = self.pending_lanes.entry(stream_id).or_insert((priority, 0));
+            lane.1 += 1;
+            lane.0
+        };
+        self.pending_frames.push_back(priority, frame);
         if let Async::NotReady = self.send_all()? {
             return Ok(Async::NotReady);
         }
@@ -272,7 +318,7 @@ where
             if self.local_go_away {
                 let flags = Flags::from(Flag::Rst);
                 let frame = Frame::new_window_update(flags, stream_id, 0);
-                self.send_frame(frame)?;
+                self.send_frame(frame, Priority::High)?;
                 debug!(
                     "[{:?}] local go away send Reset to remote stream_id={}",
                     self.ty, stream_id
@@ -355,6 +401,7 @@ where
                 }
                 Err(err) => {
                     warn!("[{:?}] Session recv_frames error: {:?}", self.ty, err);
+                    self.streams.clear();
                     return Err(err);
                 }
             }
@@ -364,8 +411,8 @@ where
     fn handle_event(&mut self, event: StreamEvent) -> Result<(), io::Error> {
         debug!("[{:?}] Session::handle_event({:?})", self.ty, event);
         match event {
-            StreamEvent::Frame(frame) => {
-                self.send_frame(frame)?;
+            StreamEvent::Frame(frame, priority) => {
+                self.send_frame(frame, priority)?;
             }
             StreamEvent::StateChanged((stream_id, state)) => {
                 match state {
@@ -427,6 +474,8 @@ where
             // FIXME: Optmize this loop (use futures channel ?)
 
             if self.is_dead() {
+                // The streams can still read the frames received
+                self.streams.clear();
                 return Ok(Async::Ready(None));
             }
 
@@ -478,6 +527,7 @@ where
                 debug!("[{:?}] A stream is ready", self.ty);
                 return Ok(Async::Ready(Some(stream)));
             } else if self.is_dead() {
+                self.streams.clear();
                 return Ok(Async::Ready(None));
             } else if keep_alive_not_ready || recv_frames_not_ready || recv_events_not_ready {
                 return Ok(Async::NotReady);
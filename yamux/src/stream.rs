@@ -13,6 +13,7 @@ use tokio::prelude::{AsyncRead, AsyncWrite};
 use crate::{
     error::Error,
     frame::{Flag, Flags, Frame, Type},
+    priority::Priority,
     StreamId,
 };
 
@@ -25,6 +26,8 @@ pub struct StreamHandle {
     recv_window: u32,
     send_window: u32,
     data_buf: BytesMut,
+    // Priority of the frames written from now on
+    priority: Priority,
 
     // Send stream event to parent session
     event_sender: Sender<StreamEvent>,
@@ -52,6 +55,7 @@ impl StreamHandle {
             recv_window: recv_window_size,
             send_window: send_window_size,
             data_buf: BytesMut::default(),
+            priority: Priority::default(),
             event_sender,
             frame_receiver,
         }
@@ -73,6 +77,17 @@ impl StreamHandle {
     pub fn send_window(&self) -> u32 {
         self.send_window
     }
+    /// Get the priority of the frames written
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+    /// Set the priority of the frames written from now on.
+    ///
+    /// The frames of a stream are always sent in order, the new priority takes effect
+    /// after the queued frames of the stream are sent
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.priority = priority;
+    }
 
     fn close(&mut self) -> Result<(), Error> {
         match self.state {
@@ -107,7 +122,7 @@ impl StreamHandle {
 
     #[inline]
     fn send_frame(&mut self, frame: Frame) -> Result<(), Error> {
-        let event = StreamEvent::Frame(frame);
+        let event = StreamEvent::Frame(frame, self.priority);
         self.send_event(event)
     }
 
@@ -256,7 +271,8 @@ impl io::Read for StreamHandle {
         // TODO: error handling
         // TODO: check stream state
         match self.state {
-            StreamState::RemoteClosing | StreamState::Closed => {
+            // The data received before remote closing can still be read
+            StreamState::RemoteClosing | StreamState::Closed if self.data_buf.is_empty() => {
                 debug!("closed(EOF)");
                 let _ = self.close();
                 return Err(io::ErrorKind::UnexpectedEof.into());
@@ -276,7 +292,7 @@ impl io::Read for StreamHandle {
         );
 
         match self.state {
-            StreamState::RemoteClosing | StreamState::Closed => {
+            StreamState::RemoteClosing | StreamState::Closed if self.data_buf.is_empty() => {
                 debug!("closed(EOF)");
                 let _ = self.close();
                 return Err(io::ErrorKind::UnexpectedEof.into());
@@ -291,6 +307,10 @@ impl io::Read for StreamHandle {
 
         let n = ::std::cmp::min(buf.len(), self.data_buf.len());
         if n == 0 {
+            if rv.is_err() {
+                // The session is gone, no more data will be received
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
             return Err(io::ErrorKind::WouldBlock.into());
         }
         let b = self.data_buf.split_to(n);
@@ -302,7 +322,9 @@ impl io::Read for StreamHandle {
             self.data_buf.len()
         );
         buf[..n].copy_from_slice(&b);
-        if self.send_window_update().is_err() {
+        // Remote will not send any more data after closing
+        let remote_closed = matches!(self.state, StreamState::RemoteClosing | StreamState::Closed);
+        if !remote_closed && self.send_window_update().is_err() {
             return Err(io::ErrorKind::BrokenPipe.into());
         }
         Ok(n)
@@ -378,7 +400,7 @@ impl AsyncWrite for StreamHandle {
 // Stream event
 #[derive(Debug)]
 pub(crate) enum StreamEvent {
-    Frame(Frame),
+    Frame(Frame, Priority),
     StateChanged((StreamId, StreamState)),
     // Flush stream's frames to remote stream, with a channel for sync
     Flush(StreamId),